@@ -0,0 +1,16 @@
+/*
+ * Something the player does with their turn, independent of the key that asked for it.
+ * State::perform takes these, which is also how the game is driven without a window.
+ */
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Action {
+    Move(i32, i32),
+    // Use whatever is equipped in the slot towards a direction
+    UseEquipped(usize, (i32, i32)),
+    Wait,
+    PickUp,
+    // Drop the item at this index of the player's container
+    Drop(usize),
+    // Use the item at this index of the player's container on the player
+    UseItem(usize),
+}
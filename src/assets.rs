@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::sync::Arc;
+
+use rltk::{XpColor, XpFile};
+
+pub enum AssetError {
+    Manifest(String, String),
+    NotInManifest(String),
+    Io(String, io::Error),
+}
+
+impl fmt::Display for AssetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Manifest(file, e) => write!(f, "Could not read asset manifest {}: {}", file, e),
+            Self::NotInManifest(name) => write!(f, "No asset named {} in the manifest", name),
+            Self::Io(path, e) => write!(f, "Could not load the file {}: {}", path, e),
+        }
+    }
+}
+
+/*
+ * Xp art keyed by logical names from the asset manifest (name -> file path).
+ * Files are only read the first time they are requested, anything that fails to load
+ * is reported once and replaced by a placeholder sprite.
+ */
+pub struct AssetManager {
+    manifest: HashMap<String, String>,
+    loaded: HashMap<String, Arc<XpFile>>,
+    placeholder: Arc<XpFile>,
+}
+
+impl AssetManager {
+
+    pub fn new(manifest: HashMap<String, String>) -> Self {
+        AssetManager {
+            manifest,
+            loaded: HashMap::new(),
+            placeholder: Arc::new(Self::make_placeholder()),
+        }
+    }
+
+    pub fn load_manifest(file_name: &str) -> Result<Self, AssetError> {
+        let mut contents = String::new();
+        File::open(file_name)
+            .and_then(|mut f| f.read_to_string(&mut contents))
+            .map_err(|e| AssetError::Manifest(file_name.to_string(), e.to_string()))?;
+
+        let manifest = serde_json::from_str(&contents)
+            .map_err(|e| AssetError::Manifest(file_name.to_string(), e.to_string()))?;
+
+        Ok(Self::new(manifest))
+    }
+
+    pub fn try_get(&mut self, name: &str) -> Result<Arc<XpFile>, AssetError> {
+        if let Some(art) = self.loaded.get(name) {
+            return Ok(art.clone());
+        }
+
+        let path = self.manifest
+            .get(name)
+            .ok_or_else(|| AssetError::NotInManifest(name.to_string()))?;
+
+        let art = File::open(path)
+            .and_then(|mut f| XpFile::read(&mut f))
+            .map_err(|e| AssetError::Io(path.clone(), e))?;
+
+        let art = Arc::new(art);
+        self.loaded.insert(name.to_string(), art.clone());
+        Ok(art)
+    }
+
+    // Like try_get but reports the error and falls back to the placeholder
+    pub fn get(&mut self, name: &str) -> Arc<XpFile> {
+        match self.try_get(name) {
+            Ok(art) => art,
+            Err(e) => {
+                eprintln!("{}", e);
+                // Remember the placeholder so the error is only reported once
+                self.loaded.insert(name.to_string(), self.placeholder.clone());
+                self.placeholder.clone()
+            }
+        }
+    }
+
+    fn make_placeholder() -> XpFile {
+        const SIZE: usize = 9;
+        let mut xp = XpFile::new(SIZE, SIZE);
+        let magenta = XpColor::new(255, 0, 255);
+        let layer = &mut xp.layers[0];
+
+        for x in 0..SIZE {
+            for y in 0..SIZE {
+                let cell = layer.get_mut(x, y).unwrap();
+                let border = x == 0 || y == 0 || x == SIZE - 1 || y == SIZE - 1;
+                if border {
+                    cell.ch = rltk::to_cp437('#') as u32;
+                    cell.fg = magenta;
+                } else if x == SIZE / 2 && y == SIZE / 2 {
+                    cell.ch = rltk::to_cp437('?') as u32;
+                    cell.fg = magenta;
+                }
+            }
+        }
+
+        xp
+    }
+
+}
@@ -0,0 +1,268 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::map_editor::MEEntity;
+use crate::structs::TileType;
+
+// Oldest undo steps are forgotten past either of these
+const MAX_STEPS: usize = 200;
+const MAX_CELL_EDITS: usize = 100_000;
+
+/*
+ * A single cell of the map changing, with what was there before so it can be taken back.
+ */
+#[derive(Clone)]
+pub enum CellEdit {
+    Tile { idx: usize, before: TileType, after: TileType },
+    Entity { idx: usize, before: Option<MEEntity>, after: Option<MEEntity> },
+}
+
+impl CellEdit {
+
+    fn apply(&self, tiles: &mut [TileType], entities: &mut [Option<MEEntity>], forwards: bool) {
+        match self {
+            CellEdit::Tile { idx, before, after } => {
+                tiles[*idx] = if forwards { *after } else { *before };
+            }
+            CellEdit::Entity { idx, before, after } => {
+                entities[*idx] = if forwards { after.clone() } else { before.clone() };
+            }
+        }
+    }
+
+    // Which cell the edit changes, and whether it is the entity on it rather than the tile
+    fn cell(&self) -> (bool, usize) {
+        match self {
+            CellEdit::Tile { idx, .. } => (false, *idx),
+            CellEdit::Entity { idx, .. } => (true, *idx),
+        }
+    }
+
+}
+
+// Everything one undo takes back: a click, a whole drag stroke or a fill
+#[derive(Default)]
+struct EditStep {
+    id: u64,
+    edits: Vec<CellEdit>,
+    // Where each cell's edit is in edits, only kept while the step is open
+    cells: HashMap<(bool, usize), usize>,
+}
+
+/*
+ * Undo and redo stacks for the map editor.
+ * Edits are gathered into the open step until it is ended, so a drag stroke undoes in one go.
+ * Steps get increasing ids, the map is unchanged since the last save while the newest applied step is the one it was saved at.
+ */
+#[derive(Default)]
+pub struct EditHistory {
+    undo: VecDeque<EditStep>,
+    redo: Vec<EditStep>,
+    open: Option<EditStep>,
+    next_id: u64,
+    // Id of the newest step dropped off the bottom of the undo stack, 0 if none was
+    forgotten_id: u64,
+    saved_id: u64,
+}
+
+impl EditHistory {
+
+    fn current_id(&self) -> u64 {
+        self.undo.back().map(|s| s.id).unwrap_or(self.forgotten_id)
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.open.as_ref().is_some_and(|s| !s.edits.is_empty()) || self.current_id() != self.saved_id
+    }
+
+    pub fn mark_saved(&mut self) {
+        self.end_step();
+        self.saved_id = self.current_id();
+    }
+
+    // Forgets everything, for when a different map is loaded
+    pub fn clear(&mut self) {
+        *self = EditHistory::default();
+    }
+
+    // Forgets everything after a change that can't be undone, leaving the map unsaved
+    pub fn clear_unsaved(&mut self) {
+        self.clear();
+        self.saved_id = u64::MAX;
+    }
+
+    /*
+     * Adds an edit that has already been made to the open step, opening one if needed.
+     * Editing a cell twice in a step keeps the first before and the last after.
+     */
+    pub fn record(&mut self, edit: CellEdit) {
+        let step = self.open.get_or_insert_with(EditStep::default);
+        match step.cells.get(&edit.cell()) {
+            Some(&i) => match (&mut step.edits[i], edit) {
+                (CellEdit::Tile { after, .. }, CellEdit::Tile { after: new_after, .. }) => *after = new_after,
+                (CellEdit::Entity { after, .. }, CellEdit::Entity { after: new_after, .. }) => *after = new_after,
+                _ => {}
+            },
+            None => {
+                step.cells.insert(edit.cell(), step.edits.len());
+                step.edits.push(edit);
+            }
+        }
+    }
+
+    // Closes the open step, making it the newest undo step if it changed anything
+    pub fn end_step(&mut self) {
+        let mut step = match self.open.take() {
+            Some(step) if !step.edits.is_empty() => step,
+            _ => return,
+        };
+        self.next_id += 1;
+        step.id = self.next_id;
+        step.cells = HashMap::new();
+        self.undo.push_back(step);
+        self.redo.clear();
+
+        while self.undo.len() > MAX_STEPS || (self.undo.len() > 1 && self.cell_edits() > MAX_CELL_EDITS) {
+            if let Some(dropped) = self.undo.pop_front() {
+                self.forgotten_id = dropped.id;
+            }
+        }
+    }
+
+    fn cell_edits(&self) -> usize {
+        self.undo.iter().map(|s| s.edits.len()).sum()
+    }
+
+    // Takes back the newest step, returns false if there was nothing to undo
+    pub fn undo(&mut self, tiles: &mut [TileType], entities: &mut [Option<MEEntity>]) -> bool {
+        self.end_step();
+        let step = match self.undo.pop_back() {
+            Some(step) => step,
+            None => return false,
+        };
+        for edit in step.edits.iter().rev() {
+            edit.apply(tiles, entities, false);
+        }
+        self.redo.push(step);
+        true
+    }
+
+    // Redoes the newest undone step, returns false if there was nothing to redo
+    pub fn redo(&mut self, tiles: &mut [TileType], entities: &mut [Option<MEEntity>]) -> bool {
+        self.end_step();
+        let step = match self.redo.pop() {
+            Some(step) => step,
+            None => return false,
+        };
+        for edit in step.edits.iter() {
+            edit.apply(tiles, entities, true);
+        }
+        self.undo.push_back(step);
+        true
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::structs::Display;
+
+    use super::*;
+
+    fn floor() -> TileType {
+        TileType::Floor(Display { glyph: '.' as u16, fg: rltk::WHITE, bg: rltk::BLACK })
+    }
+
+    fn wall() -> TileType {
+        TileType::Wall(Display { glyph: '#' as u16, fg: rltk::WHITE, bg: rltk::BLACK })
+    }
+
+    fn tile_edit(tiles: &mut [TileType], history: &mut EditHistory, idx: usize, after: TileType) {
+        let before = tiles[idx];
+        tiles[idx] = after;
+        history.record(CellEdit::Tile { idx, before, after });
+    }
+
+    #[test]
+    fn strokes_undo_in_one_step() {
+        let mut history = EditHistory::default();
+        let mut tiles = vec![floor(); 4];
+        let mut entities = vec![None; 4];
+
+        tile_edit(&mut tiles, &mut history, 0, wall());
+        tile_edit(&mut tiles, &mut history, 1, wall());
+        history.end_step();
+        tile_edit(&mut tiles, &mut history, 2, wall());
+        history.end_step();
+
+        assert!(history.undo(&mut tiles, &mut entities));
+        assert!(tiles[0] == wall() && tiles[1] == wall() && tiles[2] == floor());
+        assert!(history.undo(&mut tiles, &mut entities));
+        assert!(tiles.iter().all(|t| *t == floor()));
+        assert!(!history.undo(&mut tiles, &mut entities));
+
+        assert!(history.redo(&mut tiles, &mut entities));
+        assert!(tiles[0] == wall() && tiles[1] == wall() && tiles[2] == floor());
+    }
+
+    #[test]
+    fn new_edits_clear_redo() {
+        let mut history = EditHistory::default();
+        let mut tiles = vec![floor(); 4];
+        let mut entities = vec![None; 4];
+
+        tile_edit(&mut tiles, &mut history, 0, wall());
+        history.end_step();
+        history.undo(&mut tiles, &mut entities);
+        tile_edit(&mut tiles, &mut history, 3, wall());
+        history.end_step();
+
+        assert!(!history.redo(&mut tiles, &mut entities));
+        assert!(tiles[0] == floor() && tiles[3] == wall());
+    }
+
+    #[test]
+    fn unsaved_changes_follow_undo_and_redo() {
+        let mut history = EditHistory::default();
+        let mut tiles = vec![floor(); 4];
+        let mut entities = vec![None; 4];
+        assert!(!history.is_dirty());
+
+        tile_edit(&mut tiles, &mut history, 0, wall());
+        assert!(history.is_dirty());
+        history.mark_saved();
+        assert!(!history.is_dirty());
+
+        history.undo(&mut tiles, &mut entities);
+        assert!(history.is_dirty());
+        history.redo(&mut tiles, &mut entities);
+        assert!(!history.is_dirty());
+    }
+
+    #[test]
+    fn editing_a_cell_twice_keeps_one_edit() {
+        let mut history = EditHistory::default();
+        let mut tiles = vec![floor(); 2];
+        let mut entities = vec![None; 2];
+        let door = TileType::Wall(Display { glyph: '+' as u16, fg: rltk::WHITE, bg: rltk::BLACK });
+
+        tile_edit(&mut tiles, &mut history, 0, wall());
+        tile_edit(&mut tiles, &mut history, 1, wall());
+        tile_edit(&mut tiles, &mut history, 0, door);
+        history.end_step();
+        assert_eq!(history.undo[0].edits.len(), 2);
+
+        history.undo(&mut tiles, &mut entities);
+        assert!(tiles.iter().all(|t| *t == floor()));
+        history.redo(&mut tiles, &mut entities);
+        assert!(tiles[0] == door && tiles[1] == wall());
+    }
+
+    #[test]
+    fn cleared_history_can_stay_unsaved() {
+        let mut history = EditHistory::default();
+        history.clear_unsaved();
+        assert!(history.is_dirty());
+        history.mark_saved();
+        assert!(!history.is_dirty());
+    }
+}
@@ -0,0 +1,487 @@
+use std::fs;
+use std::path::Path;
+
+use rltk::{Rltk, VirtualKeyCode};
+
+use crate::editor_prompt::typed_char;
+use crate::map_editor::MAP_VIEW_WIDTH;
+use crate::mapgen::{self, MapSource};
+use crate::structs::map_utils::MapDescriptor;
+use crate::structs::TileType;
+
+// New maps are saved here, maps next to the executable can still be opened
+pub const MAPS_DIR: &str = "maps";
+pub const DESTINATIONS_FILE: &str = "destinations.json";
+
+const MAX_NAME_LENGTH: usize = 24;
+// Bigger maps than the view scroll in the editor
+pub const MAX_MAP_SIZE: i32 = 200;
+const LIST_ROWS: usize = 20;
+
+pub fn read_map(file_name: &str) -> Result<MapDescriptor, String> {
+    let contents = fs::read_to_string(file_name).map_err(|e| format!("Could not open {}: {}", file_name, e))?;
+    let md: MapDescriptor = serde_json::from_str(&contents).map_err(|e| format!("{} is not a map: {}", file_name, e))?;
+    if md.width <= 0 || md.height <= 0 || md.tiles.len() != (md.width * md.height) as usize || md.entities.len() != md.tiles.len() {
+        return Err(format!("{} doesn't hold a {}x{} map", file_name, md.width, md.height));
+    }
+    Ok(md)
+}
+
+pub fn write_map(file_name: &str, md: &MapDescriptor) -> Result<(), String> {
+    if let Some(dir) = Path::new(file_name).parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    let contents = serde_json::to_string(md).map_err(|e| e.to_string())?;
+    fs::write(file_name, contents).map_err(|e| format!("Could not save {}: {}", file_name, e))
+}
+
+fn map_files_in(dir: &str) -> Vec<String> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    let mut files = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "map"))
+        .filter_map(|path| path.file_name().map(|name| name.to_string_lossy().to_string()))
+        .map(|name| if dir == "." { name } else { format!("{}/{}", dir, name) })
+        .collect::<Vec<_>>();
+    files.sort();
+    files
+}
+
+// Every map in the maps directory, then the ones in the working directory
+pub fn list_maps() -> Vec<String> {
+    let mut files = map_files_in(MAPS_DIR);
+    files.extend(map_files_in("."));
+    files
+}
+
+// Where a map saved under a typed name goes, bare names land in the maps directory
+pub fn save_path(name: &str) -> String {
+    let name = name.trim();
+    let name = if name.ends_with(".map") { name.to_string() } else { format!("{}.map", name) };
+    if name.contains('/') { name } else { format!("{}/{}", MAPS_DIR, name) }
+}
+
+fn same_file(a: &str, b: &str) -> bool {
+    a.trim_start_matches("./") == b.trim_start_matches("./")
+}
+
+/*
+ * Grows or shrinks a map to width by height, anchor picks the side it grows from:
+ * (0, 0) keeps the top left corner where it is, (1, 1) keeps the map centered and (2, 2) the bottom right.
+ * New cells get blank, whatever ends up outside is dropped. Returns the new map and how far everything moved.
+ */
+pub fn resize(md: &MapDescriptor, width: i32, height: i32, anchor: (i32, i32), blank: TileType) -> (MapDescriptor, (i32, i32)) {
+    let shift = ((width - md.width) * anchor.0 / 2, (height - md.height) * anchor.1 / 2);
+    let mut tiles = vec![blank; (width * height) as usize];
+    let mut entities = vec![None; (width * height) as usize];
+
+    for y in 0..md.height {
+        for x in 0..md.width {
+            let (nx, ny) = (x + shift.0, y + shift.1);
+            if nx < 0 || nx >= width || ny < 0 || ny >= height {
+                continue;
+            }
+            let (from, to) = ((y * md.width + x) as usize, (ny * width + nx) as usize);
+            tiles[to] = md.tiles[from];
+            entities[to] = md.entities[from].clone();
+        }
+    }
+
+    let player_start = md.player_start
+        .map(|(x, y)| (x + shift.0, y + shift.1))
+        .filter(|(x, y)| *x >= 0 && *x < width && *y >= 0 && *y < height);
+
+    (MapDescriptor { width, height, tiles, entities, player_start }, shift)
+}
+
+// Moves where portals leading to any of destinations come out, true if there were any
+pub fn shift_portals(tiles: &mut [TileType], destinations: &[usize], shift: (i32, i32)) -> bool {
+    let mut moved = false;
+    for tile in tiles.iter_mut() {
+        if let TileType::Portal(_, destination, x, y) = tile {
+            if destinations.contains(destination) {
+                *x += shift.0;
+                *y += shift.1;
+                moved = true;
+            }
+        }
+    }
+    moved
+}
+
+// The portal destinations that lead to file_name
+pub fn destinations_of(file_name: &str) -> Vec<usize> {
+    mapgen::load_destinations(DESTINATIONS_FILE)
+        .iter()
+        .enumerate()
+        .filter(|(_, source)| matches!(source, MapSource::File(f) if same_file(f, file_name)))
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/*
+ * How far a map has moved since it was last saved, and how much of that the portals
+ * leading into it from each other map have already been moved by.
+ */
+#[derive(Default)]
+pub struct PortalShift {
+    total: (i32, i32),
+    done: Vec<(String, (i32, i32))>,
+}
+
+impl PortalShift {
+
+    pub fn add(&mut self, shift: (i32, i32)) {
+        self.total = (self.total.0 + shift.0, self.total.1 + shift.1);
+    }
+
+    pub fn clear(&mut self) {
+        *self = PortalShift::default();
+    }
+
+    // What the portals in other still have to be moved by
+    pub fn pending(&self, other: &str) -> (i32, i32) {
+        let done = self.done.iter().find(|(f, _)| same_file(f, other)).map(|(_, d)| *d).unwrap_or((0, 0));
+        (self.total.0 - done.0, self.total.1 - done.1)
+    }
+
+    pub fn record(&mut self, other: &str, applied: (i32, i32)) {
+        let pending = self.pending(other);
+        let done = (self.total.0 - pending.0 + applied.0, self.total.1 - pending.1 + applied.1);
+        self.done.retain(|(f, _)| !same_file(f, other));
+        self.done.push((other.to_string(), done));
+    }
+
+}
+
+// Another map with its portals moved, waiting to be written
+pub struct ShiftedMap {
+    pub file_name: String,
+    pub md: MapDescriptor,
+    pub shift: (i32, i32),
+}
+
+/*
+ * Every other destination map with portals leading into file_name, moved by what they still have
+ * to be moved by and ready to be written. Nothing is written here, so a map that fails to load changes nothing.
+ */
+pub fn maps_to_shift(file_name: &str, shifts: &PortalShift) -> Result<Vec<ShiftedMap>, String> {
+    let into = destinations_of(file_name);
+    if into.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let mut maps: Vec<ShiftedMap> = vec![];
+    for source in mapgen::load_destinations(DESTINATIONS_FILE) {
+        let other = match source {
+            MapSource::File(other) if !same_file(&other, file_name) && !maps.iter().any(|m| same_file(&m.file_name, &other)) => other,
+            _ => continue,
+        };
+        let shift = shifts.pending(&other);
+        if shift == (0, 0) {
+            continue;
+        }
+        let mut md = read_map(&other)?;
+        if shift_portals(&mut md.tiles, &into, shift) {
+            maps.push(ShiftedMap { file_name: other, md, shift });
+        }
+    }
+    Ok(maps)
+}
+
+// Width and height being typed in, Tab moves between them
+struct SizeFields {
+    width: String,
+    height: String,
+    on_height: bool,
+}
+
+impl SizeFields {
+
+    fn new(width: i32, height: i32) -> Self {
+        SizeFields { width: width.to_string(), height: height.to_string(), on_height: false }
+    }
+
+    fn on_key(&mut self, key: VirtualKeyCode) {
+        let field = if self.on_height { &mut self.height } else { &mut self.width };
+        match key {
+            VirtualKeyCode::Tab => self.on_height = !self.on_height,
+            VirtualKeyCode::Back => { field.pop(); }
+            _ => {
+                if let Some(c) = typed_char(key, false).filter(|c| c.is_ascii_digit()) {
+                    if field.len() < 3 {
+                        field.push(c);
+                    }
+                }
+            }
+        }
+    }
+
+    fn parse(&self) -> Result<(i32, i32), String> {
+        let width = self.width.parse::<i32>().unwrap_or(0);
+        let height = self.height.parse::<i32>().unwrap_or(0);
+        if !(1..=MAX_MAP_SIZE).contains(&width) || !(1..=MAX_MAP_SIZE).contains(&height) {
+            return Err(format!("Maps can be 1x1 up to {}x{}", MAX_MAP_SIZE, MAX_MAP_SIZE));
+        }
+        Ok((width, height))
+    }
+
+    fn render(&self, ctx: &mut Rltk, x: i32, y: i32) {
+        let line = |label: &str, value: &str, focused: bool| {
+            format!("{}{:<7}{}{}", if focused { "> " } else { "  " }, label, value, if focused { "_" } else { "" })
+        };
+        ctx.print(x, y, line("Width", &self.width, !self.on_height));
+        ctx.print(x, y + 1, line("Height", &self.height, self.on_height));
+    }
+
+}
+
+enum DialogKind {
+    // The first entry makes a new map instead
+    Open { files: Vec<String>, selected: usize },
+    SaveAs { name: String },
+    New { size: SizeFields },
+    Resize { size: SizeFields, anchor: (i32, i32) },
+}
+
+// What the editor should do after a key went to the dialog
+pub enum DialogEvent {
+    Stay,
+    Cancel,
+    Open(String),
+    SaveAs(String),
+    New(i32, i32),
+    Resize(i32, i32, (i32, i32)),
+}
+
+/*
+ * Keyboard driven prompts over the map for picking, naming and sizing map files.
+ * The editor does the actual work and reports back through fail if it didn't go through.
+ */
+pub struct FileDialog {
+    kind: DialogKind,
+    // Shown in the open list when switching maps would lose edits
+    unsaved: bool,
+    error: Option<String>,
+}
+
+impl FileDialog {
+
+    pub fn open(unsaved: bool) -> Self {
+        FileDialog { kind: DialogKind::Open { files: list_maps(), selected: 0 }, unsaved, error: None }
+    }
+
+    pub fn save_as(current: Option<&str>) -> Self {
+        let name = current
+            .and_then(|f| Path::new(f).file_stem())
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+        FileDialog { kind: DialogKind::SaveAs { name }, unsaved: false, error: None }
+    }
+
+    pub fn new_map(unsaved: bool) -> Self {
+        FileDialog { kind: DialogKind::New { size: SizeFields::new(32, 32) }, unsaved, error: None }
+    }
+
+    pub fn resize(width: i32, height: i32) -> Self {
+        FileDialog { kind: DialogKind::Resize { size: SizeFields::new(width, height), anchor: (1, 1) }, unsaved: false, error: None }
+    }
+
+    pub fn fail(&mut self, error: String) {
+        self.error = Some(error);
+    }
+
+    pub fn on_key(&mut self, key: VirtualKeyCode, shift: bool) -> DialogEvent {
+        if key == VirtualKeyCode::Escape {
+            return DialogEvent::Cancel;
+        }
+        let enter = matches!(key, VirtualKeyCode::Return | VirtualKeyCode::NumpadEnter);
+
+        match &mut self.kind {
+            DialogKind::Open { files, selected } => match key {
+                VirtualKeyCode::Up => *selected = selected.saturating_sub(1),
+                VirtualKeyCode::Down => *selected = (*selected + 1).min(files.len()),
+                _ if enter && *selected == 0 => self.kind = DialogKind::New { size: SizeFields::new(32, 32) },
+                _ if enter => return DialogEvent::Open(files[*selected - 1].clone()),
+                _ => {}
+            },
+            DialogKind::SaveAs { name } => match key {
+                VirtualKeyCode::Back => { name.pop(); }
+                _ if enter && name.trim().is_empty() => self.error = Some("The map needs a name".to_string()),
+                _ if enter => return DialogEvent::SaveAs(save_path(name)),
+                _ => {
+                    if let Some(c) = typed_char(key, shift) {
+                        if name.len() < MAX_NAME_LENGTH {
+                            name.push(c);
+                        }
+                    }
+                }
+            },
+            DialogKind::New { size } => {
+                if !enter {
+                    size.on_key(key);
+                    return DialogEvent::Stay;
+                }
+                match size.parse() {
+                    Ok((width, height)) => return DialogEvent::New(width, height),
+                    Err(e) => self.error = Some(e),
+                }
+            }
+            DialogKind::Resize { size, anchor } => match key {
+                VirtualKeyCode::Left => anchor.0 = (anchor.0 - 1).max(0),
+                VirtualKeyCode::Right => anchor.0 = (anchor.0 + 1).min(2),
+                VirtualKeyCode::Up => anchor.1 = (anchor.1 - 1).max(0),
+                VirtualKeyCode::Down => anchor.1 = (anchor.1 + 1).min(2),
+                _ if enter => match size.parse() {
+                    Ok((width, height)) => return DialogEvent::Resize(width, height, *anchor),
+                    Err(e) => self.error = Some(e),
+                },
+                _ => size.on_key(key),
+            },
+        }
+        DialogEvent::Stay
+    }
+
+    pub fn render(&self, ctx: &mut Rltk) {
+        let (x, y, w) = (1, 2, MAP_VIEW_WIDTH - 3);
+        ctx.draw_box(x, y, w, LIST_ROWS as i32 + 8, rltk::WHITE, rltk::BLACK);
+        let (x, y) = (x + 2, y + 1);
+
+        let hint = match &self.kind {
+            DialogKind::Open { files, selected } => {
+                ctx.print(x, y, "Open map");
+                let first = (*selected).saturating_sub(LIST_ROWS - 1);
+                let entries = std::iter::once("New map...").chain(files.iter().map(|f| f.as_str()));
+                for (row, (i, entry)) in entries.enumerate().skip(first).take(LIST_ROWS).enumerate() {
+                    let fg = if i == *selected { rltk::YELLOW } else { rltk::WHITE };
+                    let marker = if i == *selected { "> " } else { "  " };
+                    ctx.print_color(x, y + 2 + row as i32, fg, rltk::BLACK, format!("{}{}", marker, entry));
+                }
+                "Up/Down pick, Enter opens"
+            }
+            DialogKind::SaveAs { name } => {
+                ctx.print(x, y, "Save map as");
+                ctx.print(x, y + 2, format!("{}_", name));
+                ctx.print_color(x, y + 3, rltk::GREY, rltk::BLACK, format!("saves to {}", save_path(if name.is_empty() { "..." } else { name })));
+                "Enter saves"
+            }
+            DialogKind::New { size } => {
+                ctx.print(x, y, "New map");
+                size.render(ctx, x, y + 2);
+                "Tab switches field, Enter creates"
+            }
+            DialogKind::Resize { size, anchor } => {
+                ctx.print(x, y, "Resize map");
+                size.render(ctx, x, y + 2);
+                ctx.print(x, y + 5, "Anchor");
+                for ay in 0..3 {
+                    for ax in 0..3 {
+                        let (glyph, fg) = if (ax, ay) == *anchor { ('#', rltk::YELLOW) } else { ('.', rltk::GREY) };
+                        ctx.set(x + 8 + ax * 2, y + 5 + ay, fg, rltk::BLACK, rltk::to_cp437(glyph));
+                    }
+                }
+                "Arrows move the anchor, Enter resizes"
+            }
+        };
+
+        let bottom = y + LIST_ROWS as i32 + 3;
+        if self.unsaved {
+            ctx.print_color(x, bottom, rltk::YELLOW, rltk::BLACK, "Unsaved changes will be lost");
+        }
+        if let Some(error) = &self.error {
+            ctx.print_color(x, bottom + 1, rltk::RED, rltk::BLACK, error);
+        }
+        ctx.print_color(x, bottom + 2, rltk::GREY, rltk::BLACK, format!("{}, Esc cancels", hint));
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::map_editor::MEEntity;
+    use crate::structs::Display;
+
+    use super::*;
+
+    fn floor() -> TileType {
+        TileType::Floor(Display { glyph: '.' as u16, fg: rltk::WHITE, bg: rltk::BLACK })
+    }
+
+    fn wall() -> TileType {
+        TileType::Wall(Display { glyph: '#' as u16, fg: rltk::WHITE, bg: rltk::BLACK })
+    }
+
+    fn small_map(width: i32, height: i32) -> MapDescriptor {
+        MapDescriptor {
+            width,
+            height,
+            tiles: vec![floor(); (width * height) as usize],
+            entities: vec![None; (width * height) as usize],
+            player_start: Some((0, 0)),
+        }
+    }
+
+    #[test]
+    fn resizing_keeps_the_map_around_the_anchor() {
+        let mut md = small_map(2, 2);
+        md.tiles[0] = wall();
+        md.entities[3] = Some(MEEntity { d: Display { glyph: 'g' as u16, fg: rltk::BLACK, bg: rltk::RED }, name: "Goblin".to_string() });
+
+        // Centered, growing by two each way moves everything one down and right
+        let (grown, shift) = resize(&md, 4, 4, (1, 1), floor());
+        assert_eq!(shift, (1, 1));
+        assert!(grown.tiles[5] == wall());
+        assert_eq!(grown.entities[10].as_ref().map(|e| e.name.as_str()), Some("Goblin"));
+        assert_eq!(grown.player_start, Some((1, 1)));
+
+        // Anchored bottom right, shrinking drops the top left
+        let (shrunk, shift) = resize(&md, 1, 1, (2, 2), floor());
+        assert_eq!(shift, (-1, -1));
+        assert!(shrunk.tiles[0] == floor());
+        assert!(shrunk.entities[0].is_some());
+        assert_eq!(shrunk.player_start, None);
+    }
+
+    #[test]
+    fn only_portals_into_the_resized_map_move() {
+        let look = Display { glyph: 'O' as u16, fg: rltk::WHITE, bg: rltk::BLACK };
+        let mut tiles = vec![TileType::Portal(look, 1, 4, 5), TileType::Portal(look, 2, 4, 5), floor()];
+
+        assert!(shift_portals(&mut tiles, &[1], (2, -1)));
+        assert!(tiles[0] == TileType::Portal(look, 1, 6, 4));
+        assert!(tiles[1] == TileType::Portal(look, 2, 4, 5));
+        assert!(!shift_portals(&mut tiles, &[3], (2, -1)));
+    }
+
+    #[test]
+    fn maps_saved_by_name_go_in_the_maps_directory() {
+        assert_eq!(save_path("cave"), "maps/cave.map");
+        assert_eq!(save_path("cave.map"), "maps/cave.map");
+        assert_eq!(save_path("levels/cave"), "levels/cave.map");
+    }
+
+    #[test]
+    fn portals_moved_before_a_failed_save_are_not_moved_again() {
+        let mut shifts = PortalShift::default();
+        shifts.add((2, 0));
+        shifts.record("a.map", (2, 0));
+        assert_eq!(shifts.pending("a.map"), (0, 0));
+        assert_eq!(shifts.pending("./b.map"), (2, 0));
+
+        // Resized again before the next save went through
+        shifts.add((1, 1));
+        assert_eq!(shifts.pending("a.map"), (1, 1));
+        assert_eq!(shifts.pending("b.map"), (3, 1));
+    }
+
+    #[test]
+    fn maps_can_be_bigger_than_the_view() {
+        assert_eq!(SizeFields::new(60, 40).parse(), Ok((60, 40)));
+        assert!(SizeFields::new(0, 5).parse().is_err());
+        assert!(SizeFields::new(MAX_MAP_SIZE + 1, 5).parse().is_err());
+    }
+}
@@ -0,0 +1,311 @@
+use std::fs;
+
+use rltk::{Rltk, HSV, RGB};
+
+use crate::structs::Display;
+
+pub const PRESETS_FILE: &str = "editor_presets.json";
+
+// The panel takes up the right of the window, the map is drawn left of it
+pub const PANEL_X: i32 = 44;
+
+pub const GRID_X: i32 = PANEL_X + 1;
+pub const GRID_Y: i32 = 2;
+const TARGET_Y: i32 = 19;
+pub const RGB_Y: i32 = 20;
+pub const HSV_Y: i32 = 23;
+pub const SLIDER_X: i32 = PANEL_X + 3;
+pub const SLIDER_WIDTH: i32 = 24;
+const NAMED_Y: i32 = 27;
+const RECENT_Y: i32 = 30;
+const PRESETS_Y: i32 = 32;
+const PRESETS_PER_ROW: i32 = 16;
+const PRESET_ROWS: i32 = 3;
+const MAX_RECENT: usize = 12;
+
+const NAMED_COLORS: [(&str, (u8, u8, u8)); 16] = [
+    ("black", rltk::BLACK),
+    ("white", rltk::WHITE),
+    ("grey", rltk::GREY),
+    ("slate gray", rltk::SLATE_GRAY),
+    ("red", rltk::RED),
+    ("maroon", rltk::MAROON),
+    ("orange", rltk::ORANGE),
+    ("yellow", rltk::YELLOW),
+    ("green", rltk::GREEN),
+    ("dark green", rltk::DARK_GREEN),
+    ("cyan", rltk::CYAN),
+    ("teal", rltk::TEAL),
+    ("blue", rltk::BLUE),
+    ("navy", rltk::NAVY),
+    ("purple", rltk::PURPLE),
+    ("saddle brown", rltk::SADDLEBROWN),
+];
+
+#[derive(Copy, Clone, PartialEq)]
+enum ColorTarget {
+    Fg,
+    Bg,
+}
+
+fn to_u8(c: f32) -> u8 {
+    (c.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+fn to_hsv(color: (u8, u8, u8)) -> HSV {
+    RGB::named(color).to_hsv()
+}
+
+fn from_hsv(hsv: HSV) -> (u8, u8, u8) {
+    let rgb = hsv.to_rgb();
+    (to_u8(rgb.r), to_u8(rgb.g), to_u8(rgb.b))
+}
+
+// How far along a slider x is, None if it's off the slider
+fn slider_fraction(x: i32) -> Option<f32> {
+    if !(SLIDER_X..SLIDER_X + SLIDER_WIDTH).contains(&x) {
+        return None;
+    }
+    Some((x - SLIDER_X) as f32 / (SLIDER_WIDTH - 1) as f32)
+}
+
+// Index of the two cell wide swatch under x in a row starting at start_x
+fn swatch_at(x: i32, start_x: i32, count: usize) -> Option<usize> {
+    if x < start_x {
+        return None;
+    }
+    let i = ((x - start_x) / 2) as usize;
+    if i < count { Some(i) } else { None }
+}
+
+/*
+ * Mouse driven glyph and color picking for the map editor: a CP437 grid, RGB and HSV sliders for
+ * whichever of the colors is being edited, named colors, recently painted colors and saved tile presets.
+ */
+pub struct Palette {
+    target: ColorTarget,
+    recent: Vec<(u8, u8, u8)>,
+    presets: Vec<Display>,
+}
+
+impl Palette {
+
+    pub fn new() -> Self {
+        let presets = fs::read_to_string(PRESETS_FILE)
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+        Palette {
+            target: ColorTarget::Fg,
+            recent: vec![],
+            presets,
+        }
+    }
+
+    // The panel and the line dividing it from the map
+    pub fn contains(&self, pos: (i32, i32)) -> bool {
+        pos.0 >= PANEL_X - 1
+    }
+
+    fn color_mut<'a>(&self, picked: &'a mut Display) -> &'a mut (u8, u8, u8) {
+        match self.target {
+            ColorTarget::Fg => &mut picked.fg,
+            ColorTarget::Bg => &mut picked.bg,
+        }
+    }
+
+    // Remembers the colors of a tile that was just painted
+    pub fn remember(&mut self, d: &Display) {
+        for color in [d.fg, d.bg] {
+            if self.recent.first() == Some(&color) {
+                continue;
+            }
+            self.recent.retain(|c| *c != color);
+            self.recent.insert(0, color);
+            self.recent.truncate(MAX_RECENT);
+        }
+    }
+
+    fn save_presets(&self) {
+        let written = serde_json::to_string_pretty(&self.presets)
+            .map_err(|e| e.to_string())
+            .and_then(|contents| fs::write(PRESETS_FILE, contents).map_err(|e| e.to_string()));
+        if let Err(e) = written {
+            eprintln!("Failed to save tile presets to {}: {}", PRESETS_FILE, e);
+        }
+    }
+
+    /*
+     * Handles the mouse over the panel. Sliders follow a held button,
+     * everything else only reacts to the frame a button went down.
+     */
+    pub fn on_mouse(&mut self, pos: (i32, i32), picked: &mut Display, pressed: bool, held: bool, right_pressed: bool) {
+        let (x, y) = pos;
+
+        let on_slider_row = (RGB_Y..RGB_Y + 3).contains(&y) || (HSV_Y..HSV_Y + 3).contains(&y);
+        if let (true, true, Some(f)) = (held, on_slider_row, slider_fraction(x)) {
+            let color = self.color_mut(picked);
+            match y - RGB_Y {
+                0 => color.0 = to_u8(f),
+                1 => color.1 = to_u8(f),
+                2 => color.2 = to_u8(f),
+                _ => {
+                    let mut hsv = to_hsv(*color);
+                    match y - HSV_Y {
+                        0 => hsv.h = f,
+                        1 => hsv.s = f,
+                        _ => hsv.v = f,
+                    }
+                    *color = from_hsv(hsv);
+                }
+            }
+            return;
+        }
+
+        if right_pressed && (PRESETS_Y..PRESETS_Y + PRESET_ROWS).contains(&y) {
+            let row_start = (y - PRESETS_Y) * PRESETS_PER_ROW;
+            if let Some(i) = swatch_at(x, GRID_X, PRESETS_PER_ROW as usize) {
+                let index = row_start as usize + i;
+                if index < self.presets.len() {
+                    self.presets.remove(index);
+                    self.save_presets();
+                }
+            }
+            return;
+        }
+
+        if !pressed {
+            return;
+        }
+
+        if (GRID_X..GRID_X + 16).contains(&x) && (GRID_Y..GRID_Y + 16).contains(&y) {
+            picked.glyph = ((y - GRID_Y) * 16 + (x - GRID_X)) as u16;
+        } else if y == TARGET_Y {
+            self.target = if x < PANEL_X + 14 { ColorTarget::Fg } else { ColorTarget::Bg };
+        } else if y == NAMED_Y {
+            if let Some(i) = swatch_at(x, GRID_X, NAMED_COLORS.len()) {
+                *self.color_mut(picked) = NAMED_COLORS[i].1;
+            }
+        } else if y == RECENT_Y {
+            if let Some(i) = swatch_at(x, GRID_X + 8, self.recent.len()) {
+                *self.color_mut(picked) = self.recent[i];
+            }
+        } else if y == PRESETS_Y - 1 && x >= PANEL_X + 10 {
+            if !self.presets.contains(picked) && self.presets.len() < (PRESETS_PER_ROW * PRESET_ROWS) as usize {
+                self.presets.push(*picked);
+                self.save_presets();
+            }
+        } else if (PRESETS_Y..PRESETS_Y + PRESET_ROWS).contains(&y) {
+            let row_start = (y - PRESETS_Y) * PRESETS_PER_ROW;
+            if let Some(i) = swatch_at(x, GRID_X, PRESETS_PER_ROW as usize) {
+                if let Some(preset) = self.presets.get(row_start as usize + i) {
+                    *picked = *preset;
+                }
+            }
+        }
+    }
+
+    fn draw_slider(&self, ctx: &mut Rltk, y: i32, label: &str, fraction: f32, value: String) {
+        ctx.print(PANEL_X + 1, y, label);
+        let filled = (fraction * (SLIDER_WIDTH - 1) as f32).round() as i32;
+        for i in 0..SLIDER_WIDTH {
+            let (glyph, fg) = if i == filled { ('|', rltk::YELLOW) } else { ('-', rltk::GREY) };
+            ctx.set(SLIDER_X + i, y, fg, rltk::BLACK, rltk::to_cp437(glyph));
+        }
+        ctx.print(SLIDER_X + SLIDER_WIDTH + 1, y, value);
+    }
+
+    pub fn render(&self, ctx: &mut Rltk, picked: &Display, mouse: (i32, i32)) {
+        for y in 0..crate::launcher::CONSOLE_HEIGHT as i32 - 1 {
+            ctx.set(PANEL_X - 1, y, rltk::WHITE, rltk::BLACK, rltk::to_cp437('|'));
+        }
+
+        ctx.print(PANEL_X + 1, 0, "Palette");
+        ctx.print(PANEL_X + 20, 0, "Tile:");
+        ctx.set(PANEL_X + 26, 0, picked.fg, picked.bg, picked.glyph);
+        ctx.print(PANEL_X + 28, 0, format!("#{}", picked.glyph));
+
+        for glyph in 0..256 {
+            let (x, y) = (GRID_X + glyph % 16, GRID_Y + glyph / 16);
+            let bg = if glyph as u16 == picked.glyph { rltk::DARK_GREY } else { rltk::BLACK };
+            ctx.set(x, y, rltk::WHITE, bg, glyph as u16);
+        }
+
+        let (fg_label, bg_label) = match self.target {
+            ColorTarget::Fg => ("[Foreground]", " Background "),
+            ColorTarget::Bg => (" Foreground ", "[Background]"),
+        };
+        ctx.print(PANEL_X + 1, TARGET_Y, fg_label);
+        ctx.print(PANEL_X + 14, TARGET_Y, bg_label);
+
+        let color = match self.target {
+            ColorTarget::Fg => picked.fg,
+            ColorTarget::Bg => picked.bg,
+        };
+        self.draw_slider(ctx, RGB_Y, "R", color.0 as f32 / 255.0, color.0.to_string());
+        self.draw_slider(ctx, RGB_Y + 1, "G", color.1 as f32 / 255.0, color.1.to_string());
+        self.draw_slider(ctx, RGB_Y + 2, "B", color.2 as f32 / 255.0, color.2.to_string());
+        let hsv = to_hsv(color);
+        self.draw_slider(ctx, HSV_Y, "H", hsv.h, format!("{:.2}", hsv.h));
+        self.draw_slider(ctx, HSV_Y + 1, "S", hsv.s, format!("{:.2}", hsv.s));
+        self.draw_slider(ctx, HSV_Y + 2, "V", hsv.v, format!("{:.2}", hsv.v));
+
+        for (i, (_, named)) in NAMED_COLORS.iter().enumerate() {
+            let x = GRID_X + i as i32 * 2;
+            ctx.set(x, NAMED_Y, *named, *named, rltk::to_cp437(' '));
+            ctx.set(x + 1, NAMED_Y, *named, *named, rltk::to_cp437(' '));
+        }
+        if mouse.1 == NAMED_Y {
+            if let Some(i) = swatch_at(mouse.0, GRID_X, NAMED_COLORS.len()) {
+                ctx.print(PANEL_X + 1, NAMED_Y + 1, NAMED_COLORS[i].0);
+            }
+        }
+
+        ctx.print(PANEL_X + 1, RECENT_Y, "Recent");
+        for (i, recent) in self.recent.iter().enumerate() {
+            let x = GRID_X + 8 + i as i32 * 2;
+            ctx.set(x, RECENT_Y, *recent, *recent, rltk::to_cp437(' '));
+        }
+
+        ctx.print(PANEL_X + 1, PRESETS_Y - 1, "Presets  [+ save tile]");
+        for (i, preset) in self.presets.iter().enumerate() {
+            let i = i as i32;
+            ctx.set(GRID_X + (i % PRESETS_PER_ROW) * 2, PRESETS_Y + i / PRESETS_PER_ROW, preset.fg, preset.bg, preset.glyph);
+        }
+
+        ctx.print_color(PANEL_X + 1, PRESETS_Y + PRESET_ROWS + 1, rltk::GREY, rltk::BLACK, "Right click a preset to delete it");
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn palette_picks_glyphs_from_the_grid() {
+        let mut palette = Palette::new();
+        let mut picked = Display { glyph: '.' as u16, fg: rltk::WHITE, bg: rltk::BLACK };
+
+        // '#' is 35, second row of the grid
+        palette.on_mouse((GRID_X + 3, GRID_Y + 2), &mut picked, true, true, false);
+        assert_eq!(picked.glyph, '#' as u16);
+
+        // Only the frame the button goes down picks
+        palette.on_mouse((GRID_X, GRID_Y), &mut picked, false, true, false);
+        assert_eq!(picked.glyph, '#' as u16);
+    }
+
+    #[test]
+    fn palette_sliders_edit_the_foreground() {
+        let mut palette = Palette::new();
+        let mut picked = Display { glyph: '.' as u16, fg: rltk::BLACK, bg: rltk::NAVY };
+
+        palette.on_mouse((SLIDER_X + SLIDER_WIDTH - 1, RGB_Y), &mut picked, false, true, false);
+        assert_eq!(picked.fg, (255, 0, 0));
+
+        palette.on_mouse((SLIDER_X, HSV_Y + 2), &mut picked, false, true, false);
+        assert_eq!(picked.fg, (0, 0, 0));
+        assert_eq!(picked.bg, rltk::NAVY);
+    }
+}
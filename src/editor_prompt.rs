@@ -0,0 +1,167 @@
+use rltk::{Rltk, VirtualKeyCode};
+
+const MAX_LENGTH: usize = 24;
+
+// The character a key types into a name or number, if any
+pub fn typed_char(key: VirtualKeyCode, shift: bool) -> Option<char> {
+    let code = key as u32;
+    match key {
+        VirtualKeyCode::Key0 => Some('0'),
+        _ if code <= VirtualKeyCode::Key9 as u32 => char::from_digit(code - VirtualKeyCode::Key1 as u32 + 1, 10),
+        _ if (VirtualKeyCode::A as u32..=VirtualKeyCode::Z as u32).contains(&code) => {
+            let c = (b'a' + (code - VirtualKeyCode::A as u32) as u8) as char;
+            Some(if shift { c.to_ascii_uppercase() } else { c })
+        }
+        VirtualKeyCode::Minus if shift => Some('_'),
+        VirtualKeyCode::Minus => Some('-'),
+        _ => None,
+    }
+}
+
+enum PromptKind {
+    Entity,
+    // The map cell the portal goes on
+    Portal { at: (i32, i32) },
+}
+
+// What the editor should do after a key went to the prompt
+pub enum PromptEvent {
+    Stay,
+    Cancel,
+    Entity(String),
+    Portal { at: (i32, i32), destination: usize, x: i32, y: i32 },
+}
+
+/*
+ * Asks in the window for what the editor can't pick with the mouse:
+ * the name of the monster or item to place, or where a portal leads.
+ * Tab moves between fields, anything that doesn't parse is shown in the prompt instead of taken.
+ */
+pub struct Prompt {
+    kind: PromptKind,
+    fields: Vec<(&'static str, String)>,
+    focused: usize,
+    error: Option<String>,
+}
+
+impl Prompt {
+
+    pub fn entity(current: Option<&str>) -> Self {
+        Prompt {
+            kind: PromptKind::Entity,
+            fields: vec![("Name", current.unwrap_or_default().to_string())],
+            focused: 0,
+            error: None,
+        }
+    }
+
+    pub fn portal(at: (i32, i32)) -> Self {
+        Prompt {
+            kind: PromptKind::Portal { at },
+            fields: vec![("To", String::new()), ("X", String::new()), ("Y", String::new())],
+            focused: 0,
+            error: None,
+        }
+    }
+
+    pub fn fail(&mut self, error: String) {
+        self.error = Some(error);
+    }
+
+    fn number(&self, field: usize) -> Result<i32, String> {
+        let (label, value) = &self.fields[field];
+        value.parse::<i32>().map_err(|_| format!("{} has to be a number", label))
+    }
+
+    pub fn on_key(&mut self, key: VirtualKeyCode, shift: bool) -> PromptEvent {
+        if key == VirtualKeyCode::Escape {
+            return PromptEvent::Cancel;
+        }
+        let numbers = matches!(self.kind, PromptKind::Portal { .. });
+        let field = &mut self.fields[self.focused].1;
+
+        match key {
+            VirtualKeyCode::Return | VirtualKeyCode::NumpadEnter => match self.kind {
+                PromptKind::Entity if field.trim().is_empty() => self.error = Some("Type the name of a monster or item".to_string()),
+                PromptKind::Entity => return PromptEvent::Entity(field.trim().to_string()),
+                PromptKind::Portal { at } => {
+                    let parsed = (self.number(0), self.number(1), self.number(2));
+                    match parsed {
+                        (Ok(destination), Ok(x), Ok(y)) => return PromptEvent::Portal { at, destination: destination as usize, x, y },
+                        (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => self.error = Some(e),
+                    }
+                }
+            },
+            VirtualKeyCode::Tab => self.focused = (self.focused + 1) % self.fields.len(),
+            VirtualKeyCode::Back => { field.pop(); }
+            VirtualKeyCode::Space if !numbers && field.len() < MAX_LENGTH => field.push(' '),
+            _ => {
+                let c = typed_char(key, shift).filter(|c| !numbers || c.is_ascii_digit());
+                if let (Some(c), true) = (c, field.len() < MAX_LENGTH) {
+                    field.push(c);
+                }
+            }
+        }
+        PromptEvent::Stay
+    }
+
+    pub fn render(&self, ctx: &mut Rltk) {
+        // Over the map, left of the palette
+        let (x, y, w) = (1, 2, crate::editor_palette::PANEL_X - 4);
+        ctx.draw_box(x, y, w, self.fields.len() as i32 + 6, rltk::WHITE, rltk::BLACK);
+        let (x, y) = (x + 2, y + 1);
+
+        let title = match self.kind {
+            PromptKind::Entity => "Place monster or item",
+            PromptKind::Portal { .. } => "Portal to destination",
+        };
+        ctx.print(x, y, title);
+        for (i, (label, value)) in self.fields.iter().enumerate() {
+            let focused = i == self.focused;
+            let line = format!("{}{:<6}{}{}", if focused { "> " } else { "  " }, label, value, if focused { "_" } else { "" });
+            ctx.print(x, y + 2 + i as i32, line);
+        }
+
+        let bottom = y + self.fields.len() as i32 + 3;
+        if let Some(error) = &self.error {
+            ctx.print_color(x, bottom, rltk::RED, rltk::BLACK, error);
+        }
+        let hint = if self.fields.len() > 1 { "Tab switches field, Enter sets, Esc cancels" } else { "Enter sets, Esc cancels" };
+        ctx.print_color(x, bottom + 1, rltk::GREY, rltk::BLACK, hint);
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn type_in(prompt: &mut Prompt, keys: &[VirtualKeyCode]) -> PromptEvent {
+        let mut event = PromptEvent::Stay;
+        for key in keys {
+            event = prompt.on_key(*key, false);
+        }
+        event
+    }
+
+    #[test]
+    fn portal_needs_every_field_filled_in() {
+        use VirtualKeyCode::*;
+        let mut prompt = Prompt::portal((3, 4));
+
+        assert!(matches!(type_in(&mut prompt, &[Key2, Tab, Key1, Key0, Return]), PromptEvent::Stay));
+        assert_eq!(prompt.error.as_deref(), Some("Y has to be a number"));
+
+        let event = type_in(&mut prompt, &[Tab, A, Key5, Return]);
+        assert!(matches!(event, PromptEvent::Portal { at: (3, 4), destination: 2, x: 10, y: 5 }));
+    }
+
+    #[test]
+    fn entity_names_can_have_spaces() {
+        use VirtualKeyCode::*;
+        let mut prompt = Prompt::entity(None);
+        prompt.on_key(T, true);
+        let event = type_in(&mut prompt, &[A, L, L, Space, D, Return]);
+        assert!(matches!(event, PromptEvent::Entity(name) if name == "Tall d"));
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::VecDeque;
+
+use rltk::Point;
+
+/*
+ * How a click on the map paints. The brush paints under the cursor while a button is held,
+ * the shapes are dragged out from where the button went down and only land once it comes back up.
+ */
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Tool {
+    Brush,
+    Rect,
+    FilledRect,
+    Line,
+    Fill,
+}
+
+impl Tool {
+
+    // In the order of the number keys that pick them
+    pub const ALL: [Tool; 5] = [Tool::Brush, Tool::Rect, Tool::FilledRect, Tool::Line, Tool::Fill];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Tool::Brush => "brush",
+            Tool::Rect => "rect",
+            Tool::FilledRect => "filled",
+            Tool::Line => "line",
+            Tool::Fill => "fill",
+        }
+    }
+
+    pub fn is_shape(&self) -> bool {
+        matches!(self, Tool::Rect | Tool::FilledRect | Tool::Line)
+    }
+
+    // The cells a shape dragged from one corner or end to the other covers, nothing for the other tools
+    pub fn shape(&self, from: (i32, i32), to: (i32, i32)) -> Vec<(i32, i32)> {
+        match self {
+            Tool::Rect => rect_points(from, to, false),
+            Tool::FilledRect => rect_points(from, to, true),
+            Tool::Line => line_points(from, to),
+            Tool::Brush | Tool::Fill => vec![],
+        }
+    }
+
+}
+
+pub fn rect_points(a: (i32, i32), b: (i32, i32), filled: bool) -> Vec<(i32, i32)> {
+    let (x1, x2) = (a.0.min(b.0), a.0.max(b.0));
+    let (y1, y2) = (a.1.min(b.1), a.1.max(b.1));
+    let mut points = vec![];
+    for y in y1..=y2 {
+        for x in x1..=x2 {
+            if filled || x == x1 || x == x2 || y == y1 || y == y2 {
+                points.push((x, y));
+            }
+        }
+    }
+    points
+}
+
+pub fn line_points(a: (i32, i32), b: (i32, i32)) -> Vec<(i32, i32)> {
+    if a == b {
+        return vec![a];
+    }
+    rltk::line2d_bresenham(Point::new(a.0, a.1), Point::new(b.0, b.1))
+        .into_iter()
+        .map(|p| (p.x, p.y))
+        .collect()
+}
+
+/*
+ * Indexes of every cell connected to start through its four neighbours for which same holds,
+ * start included. Empty if start is off the map or doesn't match itself.
+ */
+pub fn flood_fill(width: i32, height: i32, start: (i32, i32), same: impl Fn(usize) -> bool) -> Vec<usize> {
+    let in_bounds = |(x, y): (i32, i32)| x >= 0 && x < width && y >= 0 && y < height;
+    let idx = |(x, y): (i32, i32)| (y * width + x) as usize;
+    if !in_bounds(start) || !same(idx(start)) {
+        return vec![];
+    }
+
+    let mut seen = vec![false; (width * height) as usize];
+    let mut open = VecDeque::from([start]);
+    let mut region = vec![];
+    seen[idx(start)] = true;
+    while let Some(pos) = open.pop_front() {
+        region.push(idx(pos));
+        for (dx, dy) in [(1, 0), (-1, 0), (0, 1), (0, -1)] {
+            let next = (pos.0 + dx, pos.1 + dy);
+            if in_bounds(next) && !seen[idx(next)] && same(idx(next)) {
+                seen[idx(next)] = true;
+                open.push_back(next);
+            }
+        }
+    }
+    region
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rectangles_outline_or_fill() {
+        let outline = Tool::Rect.shape((3, 4), (0, 0));
+        assert_eq!(outline.len(), 14);
+        assert!(outline.contains(&(0, 0)) && outline.contains(&(3, 4)) && !outline.contains(&(1, 1)));
+        assert_eq!(Tool::FilledRect.shape((0, 0), (3, 4)).len(), 20);
+    }
+
+    #[test]
+    fn lines_join_both_ends() {
+        let line = line_points((0, 0), (5, 2));
+        assert_eq!(line.len(), 6);
+        assert_eq!(line.first(), Some(&(0, 0)));
+        assert_eq!(line.last(), Some(&(5, 2)));
+        assert_eq!(line_points((2, 2), (2, 2)), vec![(2, 2)]);
+    }
+
+    #[test]
+    fn flood_fill_stops_at_other_tiles() {
+        // A 5x3 map split by a wall down the middle column
+        let same = |i: usize| i % 5 != 2;
+
+        let mut region = flood_fill(5, 3, (0, 0), same);
+        region.sort();
+        assert_eq!(region, vec![0, 1, 5, 6, 10, 11]);
+        assert!(flood_fill(5, 3, (2, 1), same).is_empty());
+    }
+}
@@ -1,185 +1,89 @@
-
 pub mod entity_create {
     use crate::*;
-
-    pub fn resolve_entity_string(state: &mut State, pos: (i32, i32), str_e: &str) -> EntityIndex {
-        match str_e {
-            "Goblin" => create_goblin(state, pos),
-            "SFElemental" => create_fire_elemental(state, pos),
-            "Spider" => create_spider(state, pos),
-            "KSpider" => create_king_spider(state, pos),
-            "Crazy Eyes" => create_crazy_eyes(state, pos),
-            "Tall Dude" => create_tall_dude(state, pos),
-            "Rock" => create_rock(state, pos),
-            _ => panic!("Entity not able to be resolved")
+    use crate::raws::{AIKind, MonsterRaw};
+    use crate::scheduler::Initiative;
+    use crate::items::FloorItem;
+    use crate::loot::LootDrop;
+
+    /*
+     * Spawns the monster or item registered under str_e in the raws, monsters first,
+     * returns None if neither defines it.
+     */
+    pub fn resolve_entity_string(state: &mut State, pos: (i32, i32), str_e: &str) -> Option<EntityIndex> {
+        if let Some(raw) = state.monsters.get(str_e).cloned() {
+            return Some(spawn_monster(state, pos, &raw));
         }
+        let item = state.items.get(str_e)?.to_item();
+        Some(spawn_floor_item(state, pos, item))
     }
 
     fn basic_en(
         pos: (i32, i32),
-        glyph: u16,
-        fg: (u8, u8, u8),
-        bg: (u8, u8, u8),
+        d: Display,
     ) -> BasicEntity {
         BasicEntity {
             x: pos.0,
             y: pos.1,
-            d: Display {
-                glyph,
-                fg,
-                bg
-            },
+            d,
         }
     }
 
-    pub fn create_crazy_eyes(state: &mut State, pos: (i32, i32)) -> EntityIndex {
-        let entity_component = basic_en(pos, '%' as u16, rltk::PURPLE4, rltk::RED);
-        let ai_component = ZombieAI;
-        let mut stat_component = StatBlock::default();
-
-        stat_component.hp.set(22);
-        stat_component.atk.set(10);
-        stat_component.def.set(5);
-
-        let art = state.resources[8].clone();
-        
-        state.ecs.spawn((
-                entity_component,
-                ai_component,
-                stat_component,
-                EntityView {
-                    name: "Crazyyyy Eyes".to_string(),
-                    art: art
-                }
-        ))
-    }
-
-    pub fn create_tall_dude(state: &mut State, pos: (i32, i32)) -> EntityIndex {
-        let entity_component = basic_en(pos, '|' as u16, rltk::PURPLE4, rltk::DARKGRAY);
-        let ai_component = ZombieAI;
+    pub fn spawn_monster(state: &mut State, pos: (i32, i32), raw: &MonsterRaw) -> EntityIndex {
+        let entity_component = basic_en(pos, raw.display());
         let mut stat_component = StatBlock::default();
 
-        stat_component.hp.set(13);
-        stat_component.atk.set(20);
-        stat_component.def.set(1);
-
-        let art = state.resources[7].clone();
-        
-        state.ecs.spawn((
-                entity_component,
-                ai_component,
-                stat_component,
-                EntityView {
-                    name: "Tall Dude!".to_string(),
-                    art: art
-                }
-        ))
-    }
-    
-    pub fn create_rock(state: &mut State, pos: (i32, i32)) -> EntityIndex {
-        let entity_component = basic_en(pos, '0' as u16, rltk::GRAY56, rltk::DARKGRAY);
-        let ai_component = ZombieAI;
-        let mut stat_component = StatBlock::default();
-
-        stat_component.hp.set(47);
-        stat_component.atk.set(1);
-        stat_component.def.set(1);
-
-        let art = state.resources[6].clone();
-        
-        state.ecs.spawn((
-                entity_component,
-                ai_component,
-                stat_component,
-                EntityView {
-                    name: "Dah Rock".to_string(),
-                    art: art
-                }
-        ))
-    }
-
-    pub fn create_fire_elemental(state: &mut State, pos: (i32, i32)) -> EntityIndex {
-        let entity_component = basic_en(pos, '*' as u16, rltk::RED, rltk::BROWN2);
-        let ai_component = ZombieAI;
-        let mut stat_component = StatBlock::default();
-
-        stat_component.hp.set(15);
-        stat_component.atk.set(7);
-
-        let goblin_man_art = state.resources[1].clone();
-        
-        state.ecs.spawn((
-                entity_component,
-                ai_component,
-                stat_component,
-                EntityView {
-                    name: "S Fire Elemental".to_string(),
-                    art: goblin_man_art
-                }
-        ))
-    }
-
-    pub fn create_king_spider(state: &mut State, pos: (i32, i32)) -> EntityIndex {
-        let entity_component = basic_en(pos, 'S' as u16, rltk::BURLYWOOD, rltk::BROWN2);
-        let ai_component = ZombieAI;
-        let mut stat_component = StatBlock::default();
-
-        stat_component.hp.set(24);
-        stat_component.atk.set(7);
+        stat_component.hp.set(raw.stats.hp);
+        stat_component.atk.set(raw.stats.atk);
+        stat_component.def.set(raw.stats.def);
+
+        let mut builder = EntityBuilder::new();
+        builder.add(entity_component)
+            .add(stat_component)
+            .add(Viewshed::new(raw.vision))
+            .add(Initiative::new(raw.speed))
+            .add(EntityView {
+                name: raw.name.clone(),
+                art: raw.art.clone(),
+            });
+
+        match raw.ai {
+            AIKind::Zombie => { builder.add(ZombieAI); }
+            AIKind::Passive => {}
+        }
 
-        let goblin_man_art = state.resources[5].clone();
-        
-        state.ecs.spawn((
-                entity_component,
-                ai_component,
-                stat_component,
-                EntityView {
-                    name: "King Spider".to_string(),
-                    art: goblin_man_art
+        let loot = raw.loot.iter()
+            .filter_map(|key| {
+                let item = state.items.get(key).map(|i| i.to_item());
+                if item.is_none() {
+                    eprintln!("Unknown item {} in the loot of {}, skipping", key, raw.key);
                 }
-        ))
-    }
-
-
-    pub fn create_spider(state: &mut State, pos: (i32, i32)) -> EntityIndex {
-        let entity_component = basic_en(pos, 's' as u16, rltk::BURLYWOOD, rltk::BROWN2);
-        let ai_component = ZombieAI;
-        let mut stat_component = StatBlock::default();
-
-        stat_component.hp.set(12);
-        stat_component.atk.set(2);
+                item
+            })
+            .collect::<Vec<_>>();
+        if !loot.is_empty() {
+            builder.add(Container {
+                max_items: loot.len(),
+                items: loot,
+            });
+        }
+        if let Some(table) = &raw.loot_table {
+            builder.add(LootDrop { table: table.clone() });
+        }
+        if let Some(effect) = &raw.on_hit {
+            builder.add(OnHit { effect: (**effect).clone() });
+        }
 
-        let goblin_man_art = state.resources[4].clone();
-        
-        state.ecs.spawn((
-                entity_component,
-                ai_component,
-                stat_component,
-                EntityView {
-                    name: "Spider".to_string(),
-                    art: goblin_man_art
-                }
-        ))
+        state.ecs.spawn(builder.build())
     }
 
-    pub fn create_goblin(state: &mut State, pos: (i32, i32)) -> EntityIndex {
-        let entity_component = basic_en(pos, 'g' as u16, rltk::RED, rltk::BLACK);
-        let ai_component = ZombieAI;
-        let mut stat_component = StatBlock::default();
-
-        stat_component.hp.set(10);
-        stat_component.atk.set(1);
-
-        let goblin_man_art = state.resources[0].clone();
-        
+    pub fn spawn_floor_item(state: &mut State, pos: (i32, i32), item: Item) -> EntityIndex {
         state.ecs.spawn((
-                entity_component,
-                ai_component,
-                stat_component,
-                EntityView {
-                    name: "Goblina".to_string(),
-                    art: goblin_man_art
-                }
+            basic_en(pos, item.d),
+            EntityView {
+                name: item.name.clone(),
+                art: item.art.clone(),
+            },
+            FloorItem { item },
         ))
     }
 }
@@ -0,0 +1,117 @@
+use std::cell::Cell;
+
+use rltk::{ColorPair, DrawBatch, Point, Rltk, VirtualKeyCode, RGB};
+
+use crate::structs::{EntityView, Player, UInterface};
+use crate::{EntityIndex, State};
+
+// Oldest messages are dropped past this many
+const MAX_ENTRIES: usize = 500;
+
+pub struct LogEntry {
+    pub text: String,
+    pub color: (u8, u8, u8),
+}
+
+#[derive(Default)]
+pub struct GameLog {
+    entries: Vec<LogEntry>,
+    // How many messages back from the newest the panel is scrolled
+    pub scroll: usize,
+}
+
+impl GameLog {
+
+    pub fn push<S: Into<String>>(&mut self, text: S, color: (u8, u8, u8)) {
+        self.entries.push(LogEntry {
+            text: text.into(),
+            color,
+        });
+        if self.entries.len() > MAX_ENTRIES {
+            self.entries.remove(0);
+        }
+        // Jump back to the newest message whenever something happens
+        self.scroll = 0;
+    }
+
+    pub fn entries(&self) -> &[LogEntry] {
+        &self.entries
+    }
+
+    pub fn scroll_up(&mut self) {
+        self.scroll = (self.scroll + 1).min(self.entries.len().saturating_sub(1));
+    }
+
+    pub fn scroll_down(&mut self) {
+        self.scroll = self.scroll.saturating_sub(1);
+    }
+
+    /*
+     * Draws the newest height messages (offset by scroll) into the box at x, y,
+     * newest at the bottom, truncated to width.
+     */
+    pub fn render_panel(&self, g_db: &mut DrawBatch, x: i32, y: i32, width: usize, height: usize) {
+        draw_entries(&self.entries, self.scroll, g_db, x, y, width, height);
+    }
+
+}
+
+fn draw_entries(entries: &[LogEntry], scroll: usize, g_db: &mut DrawBatch, x: i32, y: i32, width: usize, height: usize) {
+    let end = entries.len().saturating_sub(scroll);
+    let start = end.saturating_sub(height);
+
+    for (row, entry) in entries[start..end].iter().enumerate() {
+        let text = entry.text.chars().take(width).collect::<String>();
+        g_db.print_color(
+            Point::new(x, y + row as i32),
+            text,
+            ColorPair::new(RGB::named(entry.color), RGB::named(rltk::BLACK)),
+        );
+    }
+}
+
+// How an entity is referred to in the log
+pub fn describe(state: &State, e: EntityIndex) -> String {
+    if state.ecs.get::<Player>(e).is_ok() {
+        return "You".to_string();
+    }
+    state.ecs.get::<EntityView>(e)
+        .map(|v| v.name.clone())
+        .unwrap_or_else(|_| "Something".to_string())
+}
+
+/*
+ * Full screen viewer over the whole message history.
+ */
+pub struct MessageLogUI {
+    pub scroll: Cell<usize>,
+}
+
+impl UInterface for MessageLogUI {
+    fn on_input(&self, state: &mut State, key: Option<VirtualKeyCode>) -> bool {
+        let max_scroll = state.log.entries().len().saturating_sub(1);
+        match key {
+            Some(VirtualKeyCode::Up) | Some(VirtualKeyCode::PageUp) => {
+                self.scroll.set((self.scroll.get() + 1).min(max_scroll));
+            }
+            Some(VirtualKeyCode::Down) | Some(VirtualKeyCode::PageDown) => {
+                self.scroll.set(self.scroll.get().saturating_sub(1));
+            }
+            Some(VirtualKeyCode::Escape) | Some(VirtualKeyCode::Tab) => return true,
+            _ => {}
+        }
+        false
+    }
+
+    fn render(&self, ctx: &mut Rltk, state: &State) {
+        let mut g_db = DrawBatch::new();
+        g_db.cls();
+
+        g_db.print(Point::new(0, 0), "Message log (Up/Down to scroll, Esc to close)");
+        draw_entries(state.log.entries(), self.scroll.get(), &mut g_db, 0, 2, 80, 38);
+
+        g_db.submit(0).expect("Rendering error with draw batch");
+
+        rltk::render_draw_buffer(ctx).expect("Rendering error");
+    }
+}
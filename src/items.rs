@@ -0,0 +1,235 @@
+use serde::{Deserialize, Serialize};
+
+use crate::entities::entity_create;
+use crate::gamelog;
+use crate::targeting::Targets;
+use crate::structs::{BasicEntity, Container, EffectHandler, Equipment, Item, StatBlock, StatBonuses};
+use crate::{EntityIndex, State};
+
+/*
+ * An item lying on a map tile, picking it up moves the item into a container.
+ */
+#[derive(Clone, Serialize, Deserialize)]
+pub struct FloorItem {
+    pub item: Item,
+}
+
+// The floor item on the tile at pos, the oldest one if there are several
+pub fn floor_item_at(state: &State, pos: (i32, i32)) -> Option<EntityIndex> {
+    let idx = state.map_state.xy_idx(pos.0, pos.1);
+    state.map_state.entities[idx]
+        .iter()
+        .copied()
+        .filter(|e| state.ecs.get::<FloorItem>(*e).is_ok())
+        // The tile's set has no order, replays need the same pick every time
+        .min_by_key(|e| e.to_bits())
+}
+
+// Moves the item who stands on into their container, returns false if nothing was picked up
+pub fn pick_up(state: &mut State, who: EntityIndex) -> bool {
+    let pos = state.ecs.get::<BasicEntity>(who).unwrap().pos();
+    let floor_entity = match floor_item_at(state, pos) {
+        Some(e) => e,
+        None => {
+            state.log.push("There is nothing here to pick up", rltk::GREY);
+            return false;
+        }
+    };
+
+    let item = state.ecs.get::<FloorItem>(floor_entity).unwrap().item.clone();
+    let name = item.name.clone();
+    let left_over = match state.ecs.get_mut::<Container>(who) {
+        Ok(mut container) => container.try_add_item(item),
+        Err(_) => Some(item),
+    };
+    if left_over.is_some() {
+        state.log.push(format!("{} can't carry any more", gamelog::describe(state, who)), rltk::GREY);
+        return false;
+    }
+
+    state.map_state.set_entity_from_pos(pos, floor_entity);
+    state.ecs.despawn(floor_entity).expect("Failed to despawn a floor item");
+    state.log.push(format!("{} picked up the {}", gamelog::describe(state, who), name), rltk::WHITE);
+    true
+}
+
+/*
+ * Takes the item at index out of who's container,
+ * unequipping it and shifting the equipment over the hole it leaves.
+ */
+pub fn take_item(state: &mut State, who: EntityIndex, index: usize) -> Option<Item> {
+    let item = match state.ecs.get_mut::<Container>(who) {
+        Ok(mut container) if index < container.items.len() => container.items.remove(index),
+        _ => return None,
+    };
+
+    if let Ok(mut equipment) = state.ecs.get_mut::<Equipment>(who) {
+        for slot in equipment.equips.iter_mut() {
+            *slot = match *slot {
+                Some(i) if i == index => None,
+                Some(i) if i > index => Some(i - 1),
+                other => other,
+            };
+        }
+    }
+    recompute_bonuses(state, who);
+
+    Some(item)
+}
+
+/*
+ * Writes the bonuses of everything who has equipped into their stats.
+ * An item equipped in several slots only counts once.
+ */
+pub fn recompute_bonuses(state: &mut State, who: EntityIndex) {
+    let bonuses = {
+        let (equipment, container) = match (state.ecs.get::<Equipment>(who), state.ecs.get::<Container>(who)) {
+            (Ok(equipment), Ok(container)) => (equipment, container),
+            _ => return,
+        };
+        let mut equipped = equipment.equips.iter().flatten().copied().collect::<Vec<_>>();
+        equipped.sort();
+        equipped.dedup();
+        equipped.iter()
+            .filter_map(|i| container.items.get(*i))
+            .fold(StatBonuses::default(), |total, item| StatBonuses {
+                hp: total.hp + item.bonuses.hp,
+                atk: total.atk + item.bonuses.atk,
+                def: total.def + item.bonuses.def,
+            })
+    };
+
+    if let Ok(mut stats) = state.ecs.get_mut::<StatBlock>(who) {
+        stats.hp.set_bonus(bonuses.hp);
+        stats.atk.set_bonus(bonuses.atk);
+        stats.def.set_bonus(bonuses.def);
+        // Taking off a ring of vitality shouldn't be what kills you
+        if !stats.dead && stats.hp.get_total() < 1 {
+            let missing = 1 - stats.hp.get_total();
+            stats.hp.increment(missing);
+        }
+    }
+}
+
+/*
+ * Equips the item at index of who's container in slot, moving it out of any other slot it was in.
+ * Returns false if the item doesn't fit that kind of slot.
+ */
+pub fn equip(state: &mut State, who: EntityIndex, index: usize, slot: usize) -> bool {
+    let (name, kind) = match state.ecs.get::<Container>(who).ok().and_then(|c| c.items.get(index).map(|i| (i.name.clone(), i.slot))) {
+        Some(found) => found,
+        None => return false,
+    };
+    {
+        let mut equipment = match state.ecs.get_mut::<Equipment>(who) {
+            Ok(equipment) if slot < equipment.equips.len() => equipment,
+            _ => return false,
+        };
+        let slot_kind = equipment.slot_kind(slot);
+        if slot_kind != kind {
+            drop(equipment);
+            let msg = format!("The {} doesn't go in a {} slot", name, slot_kind.name().to_lowercase());
+            state.log.push(msg, rltk::GREY);
+            return false;
+        }
+        for equipped in equipment.equips.iter_mut() {
+            if *equipped == Some(index) {
+                *equipped = None;
+            }
+        }
+        equipment.equips[slot] = Some(index);
+    }
+    recompute_bonuses(state, who);
+    true
+}
+
+pub fn unequip(state: &mut State, who: EntityIndex, slot: usize) {
+    if let Ok(mut equipment) = state.ecs.get_mut::<Equipment>(who) {
+        if let Some(equipped) = equipment.equips.get_mut(slot) {
+            *equipped = None;
+        }
+    }
+    recompute_bonuses(state, who);
+}
+
+// Puts the item at index of who's container on their tile
+pub fn drop_item(state: &mut State, who: EntityIndex, index: usize) -> bool {
+    let item = match take_item(state, who, index) {
+        Some(item) => item,
+        None => return false,
+    };
+
+    let pos = state.ecs.get::<BasicEntity>(who).unwrap().pos();
+    state.log.push(format!("{} dropped the {}", gamelog::describe(state, who), item.name), rltk::WHITE);
+    let e = entity_create::spawn_floor_item(state, pos, item);
+    state.map_state.strict_add_from_pos(pos, e);
+    true
+}
+
+// Empties who's container onto their tile
+pub fn drop_all(state: &mut State, who: EntityIndex) {
+    let items = match state.ecs.get_mut::<Container>(who) {
+        Ok(mut container) => std::mem::take(&mut container.items),
+        Err(_) => return,
+    };
+    let pos = state.ecs.get::<BasicEntity>(who).unwrap().pos();
+    for item in items {
+        let e = entity_create::spawn_floor_item(state, pos, item);
+        state.map_state.strict_add_from_pos(pos, e);
+    }
+}
+
+/*
+ * Uses the item at index of who's container on who, for potions and the like.
+ * Anything that hurts or digs has to be aimed through an equipment slot instead, so it's refused here.
+ */
+pub fn use_on_self(state: &mut State, who: EntityIndex, index: usize) -> Option<i32> {
+    let (name, helps) = {
+        let container = state.ecs.get::<Container>(who).ok()?;
+        let item = container.items.get(index)?;
+        (item.name.clone(), item.effect_chain.as_ref().map(|e| e.helps_user()))
+    };
+    match helps {
+        Some(true) => use_item(state, who, index, &Targets::entity(state, who)),
+        Some(false) => {
+            state.log.push(format!("The {} has to be aimed, equip it and use its slot", name), rltk::GREY);
+            None
+        }
+        None => {
+            state.log.push(format!("The {} can't be used, only equipped", name), rltk::GREY);
+            None
+        }
+    }
+}
+
+/*
+ * Fires the effects of the item at index of who's container at targets and spends one of its charges,
+ * removing it once it has none left. Returns the energy using it cost, None if there is no such item.
+ */
+pub fn use_item(state: &mut State, who: EntityIndex, index: usize, targets: &Targets) -> Option<i32> {
+    let (name, effect, cost, consumable) = {
+        let container = state.ecs.get::<Container>(who).ok()?;
+        let item = container.items.get(index)?;
+        (item.name.clone(), item.effect_chain.clone(), item.time_cost, item.charges.is_some())
+    };
+
+    if consumable {
+        state.log.push(format!("{} used the {}", gamelog::describe(state, who), name), rltk::WHITE);
+    }
+    if let Some(effect) = effect {
+        effect.handle_effect(state, who, targets);
+    }
+
+    let used_up = state.ecs.get_mut::<Container>(who).ok()
+        .and_then(|mut container| {
+            let charges = container.items.get_mut(index)?.charges.as_mut()?;
+            *charges -= 1;
+            Some(*charges <= 0)
+        })
+        .unwrap_or(false);
+    if used_up && take_item(state, who, index).is_some() {
+        state.log.push(format!("The {} is used up", name), rltk::GREY);
+    }
+
+    Some(cost)
+}
@@ -0,0 +1,356 @@
+use std::cell::Cell;
+use std::fs;
+
+use rltk::{ColorPair, DrawBatch, Point, Rltk, VirtualKeyCode, RGB};
+use serde::{Deserialize, Serialize};
+
+use crate::structs::UInterface;
+use crate::State;
+
+pub const KEYBINDINGS_FILE: &str = "keybindings.json";
+
+// Equipment slots that get a UseSlot command of their own
+const SLOT_COMMANDS: usize = 3;
+
+/*
+ * Everything a key can be bound to on the game screen.
+ * The move commands also pick the direction when an equipped item is being aimed.
+ */
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Command {
+    MoveN,
+    MoveNE,
+    MoveE,
+    MoveSE,
+    MoveS,
+    MoveSW,
+    MoveW,
+    MoveNW,
+    Wait,
+    PickUp,
+    // Aim the item equipped in the slot, the next direction uses it
+    UseSlot(usize),
+    Inventory,
+    CharacterSheet,
+    MessageLog,
+    LogUp,
+    LogDown,
+    Save,
+    Keybindings,
+    // Stops looking at whatever entity was clicked
+    Cancel,
+}
+
+impl Command {
+
+    pub fn all() -> Vec<Command> {
+        let mut all = vec![
+            Command::MoveN,
+            Command::MoveNE,
+            Command::MoveE,
+            Command::MoveSE,
+            Command::MoveS,
+            Command::MoveSW,
+            Command::MoveW,
+            Command::MoveNW,
+            Command::Wait,
+            Command::PickUp,
+        ];
+        all.extend((0..SLOT_COMMANDS).map(Command::UseSlot));
+        all.extend([
+            Command::Inventory,
+            Command::CharacterSheet,
+            Command::MessageLog,
+            Command::LogUp,
+            Command::LogDown,
+            Command::Save,
+            Command::Keybindings,
+            Command::Cancel,
+        ]);
+        all
+    }
+
+    pub fn direction(&self) -> Option<(i32, i32)> {
+        match self {
+            Command::MoveN => Some((0, -1)),
+            Command::MoveNE => Some((1, -1)),
+            Command::MoveE => Some((1, 0)),
+            Command::MoveSE => Some((1, 1)),
+            Command::MoveS => Some((0, 1)),
+            Command::MoveSW => Some((-1, 1)),
+            Command::MoveW => Some((-1, 0)),
+            Command::MoveNW => Some((-1, -1)),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            Command::MoveN => "Move north".to_string(),
+            Command::MoveNE => "Move north east".to_string(),
+            Command::MoveE => "Move east".to_string(),
+            Command::MoveSE => "Move south east".to_string(),
+            Command::MoveS => "Move south".to_string(),
+            Command::MoveSW => "Move south west".to_string(),
+            Command::MoveW => "Move west".to_string(),
+            Command::MoveNW => "Move north west".to_string(),
+            Command::Wait => "Wait".to_string(),
+            Command::PickUp => "Pick up".to_string(),
+            Command::UseSlot(slot) => format!("Use equipment slot {}", slot + 1),
+            Command::Inventory => "Inventory".to_string(),
+            Command::CharacterSheet => "Character sheet".to_string(),
+            Command::MessageLog => "Message log".to_string(),
+            Command::LogUp => "Scroll log up".to_string(),
+            Command::LogDown => "Scroll log down".to_string(),
+            Command::Save => "Save".to_string(),
+            Command::Keybindings => "Keybindings".to_string(),
+            Command::Cancel => "Cancel".to_string(),
+        }
+    }
+
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Preset {
+    // Arrows for the cardinals, the numpad for everything
+    Numpad,
+    // hjkl yubn, for keyboards without a numpad
+    Vi,
+    // WASD with QEZC diagonals, for left handed play
+    Wasd,
+}
+
+#[derive(Copy, Clone, Serialize, Deserialize)]
+pub struct Binding {
+    pub key: VirtualKeyCode,
+    pub command: Command,
+}
+
+/*
+ * Which key runs which command, a key runs at most one command but a command can have many keys.
+ */
+#[derive(Clone)]
+pub struct Keybindings {
+    bindings: Vec<Binding>,
+}
+
+impl Default for Keybindings {
+    fn default() -> Self {
+        Self::preset(Preset::Numpad)
+    }
+}
+
+impl Keybindings {
+
+    pub fn new(bindings: Vec<Binding>) -> Self {
+        Keybindings { bindings }
+    }
+
+    pub fn preset(preset: Preset) -> Self {
+        use VirtualKeyCode::*;
+
+        // Shared by every preset, arrows keep working as well
+        let mut keys = vec![
+            (Up, Command::MoveN),
+            (Down, Command::MoveS),
+            (Left, Command::MoveW),
+            (Right, Command::MoveE),
+            (Key1, Command::UseSlot(0)),
+            (Key2, Command::UseSlot(1)),
+            (Key3, Command::UseSlot(2)),
+            (G, Command::PickUp),
+            (I, Command::Inventory),
+            (P, Command::CharacterSheet),
+            (Tab, Command::MessageLog),
+            (PageUp, Command::LogUp),
+            (PageDown, Command::LogDown),
+            (F5, Command::Save),
+            (F1, Command::Keybindings),
+            (Escape, Command::Cancel),
+        ];
+
+        let movement = match preset {
+            Preset::Numpad => vec![
+                (Numpad8, Command::MoveN),
+                (Numpad9, Command::MoveNE),
+                (Numpad6, Command::MoveE),
+                (Numpad3, Command::MoveSE),
+                (Numpad2, Command::MoveS),
+                (Numpad1, Command::MoveSW),
+                (Numpad4, Command::MoveW),
+                (Numpad7, Command::MoveNW),
+                (Numpad5, Command::Wait),
+            ],
+            Preset::Vi => vec![
+                (K, Command::MoveN),
+                (U, Command::MoveNE),
+                (L, Command::MoveE),
+                (N, Command::MoveSE),
+                (J, Command::MoveS),
+                (B, Command::MoveSW),
+                (H, Command::MoveW),
+                (Y, Command::MoveNW),
+                (Period, Command::Wait),
+            ],
+            Preset::Wasd => vec![
+                (W, Command::MoveN),
+                (E, Command::MoveNE),
+                (D, Command::MoveE),
+                (C, Command::MoveSE),
+                (S, Command::MoveS),
+                (Z, Command::MoveSW),
+                (A, Command::MoveW),
+                (Q, Command::MoveNW),
+                (X, Command::Wait),
+            ],
+        };
+        keys.extend(movement);
+
+        Self::new(keys.into_iter().map(|(key, command)| Binding { key, command }).collect())
+    }
+
+    // The bindings in file_name, the numpad preset if there is no such file
+    pub fn load_from_file(file_name: &str) -> Self {
+        let contents = match fs::read_to_string(file_name) {
+            Ok(contents) => contents,
+            Err(_) => return Self::default(),
+        };
+        match serde_json::from_str::<Vec<Binding>>(&contents) {
+            Ok(bindings) => Self::new(bindings),
+            Err(e) => {
+                eprintln!("Malformed keybindings in {}: {}, using the default keys", file_name, e);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save_to_file(&self, file_name: &str) -> Result<(), String> {
+        let contents = serde_json::to_string_pretty(&self.bindings).map_err(|e| e.to_string())?;
+        fs::write(file_name, contents).map_err(|e| e.to_string())
+    }
+
+    pub fn bindings(&self) -> &[Binding] {
+        &self.bindings
+    }
+
+    pub fn command_for(&self, key: VirtualKeyCode) -> Option<Command> {
+        self.bindings.iter().find(|b| b.key == key).map(|b| b.command)
+    }
+
+    pub fn keys_for(&self, command: Command) -> Vec<VirtualKeyCode> {
+        self.bindings.iter().filter(|b| b.command == command).map(|b| b.key).collect()
+    }
+
+    // Binds key to command, taking it away from whatever it ran before
+    pub fn bind(&mut self, key: VirtualKeyCode, command: Command) {
+        self.bindings.retain(|b| b.key != key);
+        self.bindings.push(Binding { key, command });
+    }
+
+    pub fn clear(&mut self, command: Command) {
+        self.bindings.retain(|b| b.command != command);
+    }
+
+}
+
+/*
+ * Lists every command with its keys, Enter adds a key to the highlighted command.
+ * The bindings are written to the config file when the window is closed.
+ */
+#[derive(Default)]
+pub struct RebindUI {
+    pub selected: Cell<usize>,
+    // Waiting for the key to bind
+    pub capturing: Cell<bool>,
+}
+
+impl UInterface for RebindUI {
+    fn on_input(&self, state: &mut State, key: Option<VirtualKeyCode>) -> bool {
+        let key = match key {
+            Some(key) => key,
+            None => return false,
+        };
+        let commands = Command::all();
+        let command = commands[self.selected.get()];
+
+        if self.capturing.get() {
+            if key != VirtualKeyCode::Escape {
+                state.keys.bind(key, command);
+            }
+            self.capturing.set(false);
+            return false;
+        }
+
+        match key {
+            VirtualKeyCode::Up => {
+                self.selected.set((self.selected.get() + commands.len() - 1) % commands.len());
+            }
+            VirtualKeyCode::Down => {
+                self.selected.set((self.selected.get() + 1) % commands.len());
+            }
+            VirtualKeyCode::Return => self.capturing.set(true),
+            VirtualKeyCode::Delete | VirtualKeyCode::Back => state.keys.clear(command),
+            VirtualKeyCode::Key1 => state.keys = Keybindings::preset(Preset::Numpad),
+            VirtualKeyCode::Key2 => state.keys = Keybindings::preset(Preset::Vi),
+            VirtualKeyCode::Key3 => state.keys = Keybindings::preset(Preset::Wasd),
+            // A replay's bindings are the recorded ones, not the player's own
+            VirtualKeyCode::Escape if state.replay.is_some() => return true,
+            VirtualKeyCode::Escape => {
+                if let Err(e) = state.keys.save_to_file(KEYBINDINGS_FILE) {
+                    eprintln!("Failed to save keybindings to {}: {}", KEYBINDINGS_FILE, e);
+                }
+                return true;
+            }
+            _ => {}
+        }
+        false
+    }
+
+    fn render(&self, ctx: &mut Rltk, state: &State) {
+        let mut g_db = DrawBatch::new();
+        g_db.cls();
+
+        g_db.print(Point::new(0, 0), "Keybindings (Up/Down select, Enter add key, Del clear, Esc save and close)");
+        g_db.print(Point::new(0, 1), "Presets: 1 numpad  2 vi-keys  3 WASD");
+
+        for (i, command) in Command::all().iter().enumerate() {
+            let y = 3 + i as i32;
+            let fg = if i == self.selected.get() { rltk::YELLOW } else { rltk::WHITE };
+
+            let keys = if i == self.selected.get() && self.capturing.get() {
+                "press a key, Esc to cancel".to_string()
+            } else {
+                state.keys.keys_for(*command)
+                    .iter()
+                    .map(|k| format!("{:?}", k))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            };
+
+            g_db.print_color(
+                Point::new(1, y),
+                format!("{:<24}{}", command.name(), keys),
+                ColorPair::new(RGB::named(fg), RGB::named(rltk::BLACK)),
+            );
+        }
+
+        g_db.submit(0).expect("Rendering error with draw batch");
+
+        rltk::render_draw_buffer(ctx).expect("Rendering error");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_bind_each_key_once() {
+        for preset in [Preset::Numpad, Preset::Vi, Preset::Wasd] {
+            let keys = Keybindings::preset(preset);
+            for binding in keys.bindings() {
+                assert_eq!(keys.bindings().iter().filter(|b| b.key == binding.key).count(), 1, "{:?} bound twice in {:?}", binding.key, preset);
+            }
+        }
+    }
+}
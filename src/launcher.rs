@@ -0,0 +1,329 @@
+use std::path::Path;
+
+use rltk::{ColorPair, DrawBatch, GameState, Rltk, VirtualKeyCode, RGB};
+
+use crate::map_editor::MapEditorState;
+use crate::mapgen::{self, MapSource};
+use crate::morgue::GameOverScreen;
+use crate::replay::Replay;
+use crate::save;
+use crate::State;
+
+pub const USAGE: &str = "\
+Usage: rust-rltk [options]
+
+  --new                 Start a new game instead of showing the menu
+  --continue            Continue from the save slot instead of showing the menu
+  --editor              Open the map editor instead of showing the menu
+  --map <file>          Map a new game starts on, or the map --editor opens
+  --seed <n>            Seed for new games
+  --slot <n>            Save slot to continue from and save to, 0 by default
+  --record <file>       Record the next new game to a replay file
+  --replay <file>       Play back a replay file
+  --window-size <WxH>   Window size in pixels, at least 80x40
+  --fullscreen          Start in fullscreen
+  --help                Show this message";
+
+// Console size the game and the editor are laid out for
+pub const CONSOLE_WIDTH: u32 = 80;
+pub const CONSOLE_HEIGHT: u32 = 40;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum MenuChoice {
+    NewGame,
+    Continue,
+    MapEditor,
+    Quit,
+}
+
+const MENU_ITEMS: [(MenuChoice, &str); 4] = [
+    (MenuChoice::NewGame, "New Game"),
+    (MenuChoice::Continue, "Continue"),
+    (MenuChoice::MapEditor, "Map Editor"),
+    (MenuChoice::Quit, "Quit"),
+];
+
+#[derive(Default)]
+pub struct LaunchOptions {
+    // Skips the menu and goes straight to this
+    pub start: Option<MenuChoice>,
+    pub map: Option<String>,
+    pub seed: Option<u64>,
+    pub slot: usize,
+    pub record: Option<String>,
+    pub replay: Option<String>,
+    pub window_size: Option<(u32, u32)>,
+    pub fullscreen: bool,
+    pub help: bool,
+}
+
+fn value<'a>(args: &mut std::slice::Iter<'a, String>, name: &str) -> Result<&'a String, String> {
+    args.next().ok_or_else(|| format!("{} needs a value", name))
+}
+
+fn number<T: std::str::FromStr>(args: &mut std::slice::Iter<String>, name: &str) -> Result<T, String> {
+    let v = value(args, name)?;
+    v.parse::<T>().map_err(|_| format!("{} expects a number, got {}", name, v))
+}
+
+impl LaunchOptions {
+
+    // Parses the arguments after the program name
+    pub fn parse(args: &[String]) -> Result<LaunchOptions, String> {
+        let mut options = LaunchOptions::default();
+        let mut args = args.iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--new" => options.start = Some(MenuChoice::NewGame),
+                "--continue" => options.start = Some(MenuChoice::Continue),
+                "--editor" => options.start = Some(MenuChoice::MapEditor),
+                "--map" => options.map = Some(value(&mut args, arg)?.clone()),
+                "--seed" => options.seed = Some(number(&mut args, arg)?),
+                "--slot" => options.slot = number(&mut args, arg)?,
+                "--record" => options.record = Some(value(&mut args, arg)?.clone()),
+                "--replay" => options.replay = Some(value(&mut args, arg)?.clone()),
+                "--window-size" => {
+                    let size = value(&mut args, arg)?;
+                    let parsed = size.split_once('x')
+                        .and_then(|(w, h)| Some((w.parse::<u32>().ok()?, h.parse::<u32>().ok()?)));
+                    match parsed {
+                        Some((w, h)) if w >= CONSOLE_WIDTH && h >= CONSOLE_HEIGHT => options.window_size = Some((w, h)),
+                        Some(_) => return Err(format!("--window-size must be at least {}x{}", CONSOLE_WIDTH, CONSOLE_HEIGHT)),
+                        None => return Err(format!("--window-size expects WIDTHxHEIGHT, got {}", size)),
+                    }
+                }
+                "--fullscreen" => options.fullscreen = true,
+                "--help" | "-h" => options.help = true,
+                other => return Err(format!("Unknown argument {}", other)),
+            }
+        }
+
+        Ok(options)
+    }
+
+    // Font pixels per console cell needed to fill the requested window
+    pub fn tile_dimensions(&self) -> Option<(u32, u32)> {
+        self.window_size.map(|(w, h)| (w / CONSOLE_WIDTH, h / CONSOLE_HEIGHT))
+    }
+
+}
+
+/*
+ * The title screen, remembers the highlighted entry and the last thing that went wrong.
+ */
+pub struct MainMenu {
+    selected: usize,
+    message: Option<String>,
+    can_continue: bool,
+}
+
+impl MainMenu {
+
+    pub fn new(slot: usize, message: Option<String>) -> Self {
+        MainMenu {
+            selected: 0,
+            message,
+            can_continue: Path::new(&save::save_path(slot)).exists(),
+        }
+    }
+
+    fn item_y(i: usize) -> i32 {
+        14 + i as i32 * 2
+    }
+
+    pub fn on_input(&mut self, ctx: &Rltk) -> Option<MenuChoice> {
+        if ctx.left_click {
+            let (_, y) = ctx.mouse_pos();
+            if let Some(i) = (0..MENU_ITEMS.len()).find(|i| Self::item_y(*i) == y) {
+                self.selected = i;
+                return Some(MENU_ITEMS[i].0);
+            }
+        }
+
+        match ctx.key {
+            Some(VirtualKeyCode::Up) | Some(VirtualKeyCode::Numpad8) => {
+                self.selected = (self.selected + MENU_ITEMS.len() - 1) % MENU_ITEMS.len();
+            }
+            Some(VirtualKeyCode::Down) | Some(VirtualKeyCode::Numpad2) => {
+                self.selected = (self.selected + 1) % MENU_ITEMS.len();
+            }
+            Some(VirtualKeyCode::Return) | Some(VirtualKeyCode::NumpadEnter) | Some(VirtualKeyCode::Space) => {
+                return Some(MENU_ITEMS[self.selected].0);
+            }
+            Some(VirtualKeyCode::N) => return Some(MenuChoice::NewGame),
+            Some(VirtualKeyCode::C) => return Some(MenuChoice::Continue),
+            Some(VirtualKeyCode::E) => return Some(MenuChoice::MapEditor),
+            Some(VirtualKeyCode::Q) | Some(VirtualKeyCode::Escape) => return Some(MenuChoice::Quit),
+            _ => {}
+        }
+        None
+    }
+
+    pub fn render(&self, ctx: &mut Rltk) {
+        let mut g_db = DrawBatch::new();
+        g_db.cls();
+
+        g_db.print_color_centered(8, "R U S T - R L T K", ColorPair::new(RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK)));
+
+        for (i, (choice, label)) in MENU_ITEMS.iter().enumerate() {
+            let fg = if i == self.selected {
+                rltk::YELLOW
+            } else if *choice == MenuChoice::Continue && !self.can_continue {
+                rltk::GREY
+            } else {
+                rltk::WHITE
+            };
+            let text = if i == self.selected { format!("> {} <", label) } else { label.to_string() };
+            g_db.print_color_centered(Self::item_y(i), text, ColorPair::new(RGB::named(fg), RGB::named(rltk::BLACK)));
+        }
+
+        if let Some(message) = &self.message {
+            g_db.print_color_centered(24, message, ColorPair::new(RGB::named(rltk::RED), RGB::named(rltk::BLACK)));
+        }
+
+        g_db.print_color_centered(36, "Up/Down and Enter, or N/C/E/Q", ColorPair::new(RGB::named(rltk::GREY), RGB::named(rltk::BLACK)));
+
+        g_db.submit(0).expect("Rendering error with draw batch");
+
+        rltk::render_draw_buffer(ctx).expect("Rendering error");
+    }
+
+}
+
+enum Screen {
+    Menu(MainMenu),
+    Game(Box<State>),
+    Editor(Box<MapEditorState>),
+    GameOver(GameOverScreen),
+}
+
+/*
+ * What the window runs: the main menu, and whichever of the game or the editor was picked from it.
+ * A game ends on the game over screen, which leads back to the menu.
+ */
+pub struct Launcher {
+    screen: Screen,
+    options: LaunchOptions,
+}
+
+impl Launcher {
+
+    pub fn new(options: LaunchOptions) -> Self {
+        let mut launcher = Launcher {
+            screen: Screen::Menu(MainMenu::new(options.slot, None)),
+            options,
+        };
+
+        let started = if let Some(file_name) = launcher.options.replay.clone() {
+            Some(Self::start_replay(&file_name))
+        } else {
+            launcher.options.start.map(|choice| launcher.start(choice))
+        };
+        if let Some(started) = started {
+            launcher.show(started);
+        }
+
+        launcher
+    }
+
+    fn start_replay(file_name: &str) -> Result<Screen, String> {
+        let replay = Replay::load_from_file(file_name)?;
+        Ok(Screen::Game(Box::new(State::from_replay(replay))))
+    }
+
+    fn start(&mut self, choice: MenuChoice) -> Result<Screen, String> {
+        match choice {
+            MenuChoice::NewGame => {
+                let mut destinations = mapgen::load_destinations("destinations.json");
+                if let Some(map) = &self.options.map {
+                    if !Path::new(map).exists() {
+                        return Err(format!("Map file {} does not exist", map));
+                    }
+                    destinations[0] = MapSource::File(map.clone());
+                }
+
+                let mut state = State::new(self.options.slot, self.options.seed, destinations);
+                // Only the first new game is recorded
+                if let Some(file_name) = self.options.record.take() {
+                    state.start_recording(&file_name);
+                }
+                Ok(Screen::Game(Box::new(state)))
+            }
+            MenuChoice::Continue => {
+                let state = State::load_game(self.options.slot)?;
+                Ok(Screen::Game(Box::new(state)))
+            }
+            MenuChoice::MapEditor => {
+                let editor = match &self.options.map {
+                    Some(map) => MapEditorState::open(map)?,
+                    None => MapEditorState::browse(),
+                };
+                Ok(Screen::Editor(Box::new(editor)))
+            }
+            MenuChoice::Quit => Ok(Screen::Menu(MainMenu::new(self.options.slot, None))),
+        }
+    }
+
+    /*
+     * Writes the morgue file and wipes the save of a game that just ended,
+     * a replayed death already had both done when it was recorded.
+     */
+    fn end_game(state: &mut State) -> Option<Screen> {
+        let summary = state.game_over.take()?;
+        if state.replay.is_some() {
+            return Some(Screen::GameOver(GameOverScreen::new(summary, "Replay finished".to_string())));
+        }
+
+        if let Err(e) = save::delete_slot(state.save_slot) {
+            eprintln!("Failed to delete save slot {}: {}", state.save_slot, e);
+        }
+        let morgue = match summary.write() {
+            Ok(path) => format!("Morgue file written to {}", path),
+            Err(e) => format!("Could not write the morgue file: {}", e),
+        };
+        Some(Screen::GameOver(GameOverScreen::new(summary, morgue)))
+    }
+
+    // Switches to the started screen, or back to the menu with the reason it couldn't start
+    fn show(&mut self, started: Result<Screen, String>) {
+        self.screen = started.unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            Screen::Menu(MainMenu::new(self.options.slot, Some(e)))
+        });
+    }
+
+}
+
+impl GameState for Launcher {
+    fn tick(&mut self, ctx: &mut Rltk) {
+        match &mut self.screen {
+            Screen::Game(state) => {
+                state.tick(ctx);
+                if let Some(game_over) = Self::end_game(state) {
+                    self.screen = game_over;
+                }
+            }
+            Screen::Editor(editor) => editor.tick(ctx),
+            Screen::GameOver(game_over) => {
+                let done = game_over.on_input(ctx);
+                game_over.render(ctx);
+                if done {
+                    self.screen = Screen::Menu(MainMenu::new(self.options.slot, None));
+                }
+            }
+            Screen::Menu(menu) => {
+                let choice = menu.on_input(ctx);
+                menu.render(ctx);
+                match choice {
+                    Some(MenuChoice::Quit) => ctx.quit(),
+                    Some(choice) => {
+                        let started = self.start(choice);
+                        self.show(started);
+                    }
+                    None => {}
+                }
+            }
+        }
+    }
+}
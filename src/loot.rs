@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+use std::fs;
+
+use serde::{Deserialize, Serialize};
+
+use crate::entities::entity_create;
+use crate::gamelog;
+use crate::structs::{BasicEntity, EntityLootHandler, EntityStat, StatBlock};
+use crate::{EntityIndex, State};
+
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize, Default)]
+pub enum Rarity {
+    #[default]
+    Common,
+    Uncommon,
+    Rare,
+    Legendary,
+}
+
+impl Rarity {
+
+    // How often an entry of this tier comes up when it doesn't set its own weight
+    pub fn weight(&self) -> i32 {
+        match self {
+            Rarity::Common => 60,
+            Rarity::Uncommon => 25,
+            Rarity::Rare => 10,
+            Rarity::Legendary => 2,
+        }
+    }
+
+    pub fn color(&self) -> (u8, u8, u8) {
+        match self {
+            Rarity::Common => rltk::WHITE,
+            Rarity::Uncommon => rltk::GREEN,
+            Rarity::Rare => rltk::CYAN,
+            Rarity::Legendary => rltk::MAGENTA,
+        }
+    }
+
+}
+
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum StatKind {
+    Hp,
+    Atk,
+    Def,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub enum LootReward {
+    // Key of the item dropped on the corpse's tile
+    Item(String),
+    // Permanently raises one of the player's stats, never past cap
+    StatGrowth { stat: StatKind, amount: i32, cap: i32 },
+    Nothing,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LootEntry {
+    pub reward: LootReward,
+    #[serde(default)]
+    pub rarity: Rarity,
+    // Overrides the rarity's weight
+    #[serde(default)]
+    pub weight: Option<i32>,
+}
+
+impl LootEntry {
+    fn weight(&self) -> i32 {
+        self.weight.unwrap_or_else(|| self.rarity.weight())
+    }
+}
+
+fn default_rolls() -> u32 {
+    1
+}
+
+/*
+ * Rolled once per death: every guaranteed entry is given, then `rolls` entries are picked by weight.
+ */
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LootTable {
+    pub key: String,
+    #[serde(default = "default_rolls")]
+    pub rolls: u32,
+    #[serde(default)]
+    pub guaranteed: Vec<LootEntry>,
+    #[serde(default)]
+    pub entries: Vec<LootEntry>,
+}
+
+impl LootTable {
+
+    fn roll(&self, state: &mut State) -> Option<&LootEntry> {
+        let total: i32 = self.entries.iter().map(|e| e.weight().max(0)).sum();
+        if total <= 0 {
+            return None;
+        }
+        let mut pick = state.rng.rng().range(0, total);
+        for entry in &self.entries {
+            let weight = entry.weight().max(0);
+            if pick < weight {
+                return Some(entry);
+            }
+            pick -= weight;
+        }
+        None
+    }
+
+}
+
+impl EntityLootHandler for LootTable {
+    fn handle_loot(&self, state: &mut State, dead: EntityIndex) {
+        for entry in &self.guaranteed {
+            give(state, dead, entry);
+        }
+        for _ in 0..self.rolls {
+            if let Some(entry) = self.roll(state) {
+                give(state, dead, entry);
+            }
+        }
+    }
+}
+
+fn grow(stat: &mut EntityStat, amount: i32, cap: i32) -> i32 {
+    let gain = (stat.get_max() + amount).min(cap) - stat.get_max();
+    if gain > 0 {
+        stat.raise(gain);
+    }
+    gain.max(0)
+}
+
+fn give(state: &mut State, dead: EntityIndex, entry: &LootEntry) {
+    match &entry.reward {
+        LootReward::Item(key) => {
+            let item = match state.items.get(key) {
+                Some(raw) => raw.to_item(),
+                None => {
+                    eprintln!("Unknown item {} in a loot table, skipping", key);
+                    return;
+                }
+            };
+            let pos = state.ecs.get::<BasicEntity>(dead).unwrap().pos();
+            let msg = format!("{} dropped a {}", gamelog::describe(state, dead), item.name);
+            state.log.push(msg, entry.rarity.color());
+            let e = entity_create::spawn_floor_item(state, pos, item);
+            state.map_state.strict_add_from_pos(pos, e);
+        }
+        LootReward::StatGrowth { stat, amount, cap } => {
+            let gained = {
+                let mut stats = state.get_player_stat_block();
+                let stats: &mut StatBlock = &mut stats;
+                match stat {
+                    StatKind::Hp => grow(&mut stats.hp, *amount, *cap),
+                    StatKind::Atk => grow(&mut stats.atk, *amount, *cap),
+                    StatKind::Def => grow(&mut stats.def, *amount, *cap),
+                }
+            };
+            if gained > 0 {
+                let what = match stat {
+                    StatKind::Hp => "healthier",
+                    StatKind::Atk => "stronger",
+                    StatKind::Def => "tougher",
+                };
+                state.log.push(format!("You feel {}", what), entry.rarity.color());
+            }
+        }
+        LootReward::Nothing => {}
+    }
+}
+
+pub struct LootRegistry {
+    tables: HashMap<String, LootTable>,
+}
+
+impl LootRegistry {
+
+    // Missing or malformed tables only mean nothing drops, so they aren't fatal
+    pub fn load_from_file(file_name: &str) -> LootRegistry {
+        let parsed = fs::read_to_string(file_name)
+            .map_err(|e| e.to_string())
+            .and_then(|contents| serde_json::from_str::<Vec<LootTable>>(&contents).map_err(|e| e.to_string()));
+
+        let tables = parsed.unwrap_or_else(|e| {
+            eprintln!("Could not load loot tables from {}: {}", file_name, e);
+            vec![]
+        });
+
+        LootRegistry {
+            tables: tables.into_iter().map(|t| (t.key.clone(), t)).collect(),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&LootTable> {
+        self.tables.get(key)
+    }
+
+}
+
+/*
+ * Which loot table a monster rolls on when it dies.
+ */
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LootDrop {
+    pub table: String,
+}
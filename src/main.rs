@@ -4,22 +4,62 @@ mod entities;
 mod structs;
 mod math_utils;
 mod map_editor;
-
-use map_editor::{MapEditorState, MEEntity};
+mod edit_history;
+mod editor_palette;
+mod editor_prompt;
+mod editor_tools;
+mod editor_files;
+mod assets;
+mod pathfinding;
+mod raws;
+mod save;
+mod gamelog;
+mod scheduler;
+mod mapgen;
+mod replay;
+mod actions;
+mod launcher;
+mod keybindings;
+mod items;
+mod loot;
+mod targeting;
+mod status;
+mod progression;
+mod morgue;
+
+#[cfg(test)]
+mod tests;
+
+use map_editor::MEEntity;
+use raws::{ItemRegistry, MonsterRegistry};
+use assets::AssetManager;
+use save::SaveGame;
+use gamelog::{GameLog, MessageLogUI};
+use scheduler::Initiative;
+use math_utils::GameRng;
+use mapgen::MapSource;
+use actions::Action;
+use items::FloorItem;
+use loot::{LootDrop, LootRegistry};
+use targeting::{Targeting, Targets};
+use status::StatusEffects;
+use morgue::DeathSummary;
+use keybindings::{Command, Keybindings, RebindUI};
+use launcher::{LaunchOptions, Launcher};
+use replay::{InputFrame, InputRecorder, Replay, ReplayPlayer};
 
 use entities::entity_create;
-use hecs::{RefMut, World};
+use hecs::{EntityBuilder, RefMut, World};
 use structs::*;
 
 use std::cell::{Cell, RefCell};
 use std::fs::File;
 use std::io::{Read, Write};
 use std::path::Path;
-use std::sync::Arc;
 
 use rltk::{
     BResult, BTermBuilder, ColorPair, DrawBatch, GameState, Point, Rltk, TextBlock, TextBuilder,
-    VirtualKeyCode, XpFile, RGB,
+    VirtualKeyCode, RGB,
 };
 
 pub type EntityIndex = hecs::Entity;
@@ -29,7 +69,11 @@ pub struct State {
 
     ecs: EntityWorld,
 
-    resources: Vec<Arc<XpFile>>,
+    assets: AssetManager,
+
+    monsters: MonsterRegistry,
+    items: ItemRegistry,
+    loot_tables: LootRegistry,
 
     queued_destruction: RefCell<Vec<EntityIndex>>,
 
@@ -37,15 +81,34 @@ pub struct State {
 
     waiting_for_directional_input: bool,
     directional_callback: Option<usize>,
+    // Map position being aimed at by an item that targets a tile
+    targeting_cursor: Option<(i32, i32)>,
 
     currently_viewed_art: Option<EntityIndex>,
     currently_viewed_stat_block: Option<EntityIndex>,
 
     until_player_save: f32,
+    save_slot: usize,
 
     destination_next_tick: RefCell<Option<(usize, i32, i32)>>,
 
     open_window: Option<Box<dyn UInterface>>,
+
+    log: GameLog,
+    rng: GameRng,
+    keys: Keybindings,
+
+    recorder: Option<InputRecorder>,
+    replay: Option<ReplayPlayer>,
+
+    // Energy the action the player just took costs, spent on the next on_turn
+    action_cost: i32,
+    game_ticks: u64,
+
+    // What last hurt the player, named in the morgue file if it was fatal
+    player_last_hit_by: Option<String>,
+    // Set when the player dies, the launcher swaps the game for the game over screen
+    game_over: Option<DeathSummary>,
     
     map_state: InternalMapState,
 
@@ -57,8 +120,9 @@ impl State {
         self.open_window = None;
     }
 
-    pub fn print_image_at(&self, x: i32, y: i32, entity_view: &EntityView, ctx: &mut Rltk) {
-        ctx.render_xp_sprite(&entity_view.art, x, y);
+    pub fn print_image_at(&mut self, x: i32, y: i32, art: &str, ctx: &mut Rltk) {
+        let art = self.assets.get(art);
+        ctx.render_xp_sprite(&art, x, y);
     }
 
     pub fn queue_destruction(&self, slot: EntityIndex) {
@@ -69,164 +133,342 @@ impl State {
         self.ecs.despawn(slot).expect("Failed to dispose of entity");
     }
 
-    fn handle_directional_input(&mut self, key: VirtualKeyCode) -> bool {
-        if !self.waiting_for_directional_input {
-            return false;
-        }
-        if self.directional_callback.is_none() {
-            self.waiting_for_directional_input = false;
-            return false;
-        }
-        let mut input_dir: Option<(i32, i32)> = None;
-        match key {
-            VirtualKeyCode::Up => input_dir = Some( (0, -1) ),
-            VirtualKeyCode::Down => input_dir = Some( (0, 1) ),
-            VirtualKeyCode::Left => input_dir = Some( (-1, 0) ),
-            VirtualKeyCode::Right => input_dir = Some( (1, 0) ),
-
-            VirtualKeyCode::Numpad8 => input_dir = Some( (0, -1) ),
-            VirtualKeyCode::Numpad2 => input_dir = Some( (0, 1) ),
-            VirtualKeyCode::Numpad4 => input_dir = Some( (-1, 0) ),
-            VirtualKeyCode::Numpad6 => input_dir = Some( (1, 0) ),
-
-            VirtualKeyCode::Numpad7 => input_dir = Some( (-1, -1) ),
-            VirtualKeyCode::Numpad9 => input_dir = Some( (1, -1) ),
-            VirtualKeyCode::Numpad1 => input_dir = Some( (-1, 1) ),
-            VirtualKeyCode::Numpad3 => input_dir = Some( (1, 1) ),
-            _ => {}
-        };
-        if input_dir.is_some() {
-            let (dx, dy) = input_dir.unwrap();
-            if let Some(slot) = self.directional_callback {
-                let (px, py) = (
-                    self.ecs.get_player().get_x() + dx,
-                    self.ecs.get_player().get_y() + dy,
-                );
+    // Index into the player's container of the item in equipment slot
+    fn equipped_item(&self, slot: usize) -> Option<usize> {
+        let player_id = self.ecs.get_player_id();
+        let index = self.ecs.get::<Equipment>(player_id).ok()?.equips.get(slot).copied().flatten()?;
+        let carried = self.ecs.get::<Container>(player_id).ok()?.items.len();
+        if index < carried { Some(index) } else { None }
+    }
 
-                let mut found_entity: Option<EntityIndex> = None;
+    fn equipped_targeting(&self, slot: usize) -> Option<Targeting> {
+        let index = self.equipped_item(slot)?;
+        let player_id = self.ecs.get_player_id();
+        self.ecs.get::<Container>(player_id).ok().map(|c| c.items[index].targeting)
+    }
 
-                for (entity, query) in self.ecs.query::<(&BasicEntity, &mut StatBlock)>()
-                        .iter() {
-                    let (ex, ey) = (query.0.get_x(), query.0.get_y());
-                    if ex == px && ey == py {
-                        found_entity = Some(entity);
-                    }
-                }
+    /*
+     * Uses the item equipped in slot aimed dx, dy away from the player,
+     * returns false without using it if there's nothing equipped or the aim is out of reach.
+     */
+    fn use_equipped(&mut self, slot: usize, dx: i32, dy: i32) -> bool {
+        let (item_idx, targeting) = match (self.equipped_item(slot), self.equipped_targeting(slot)) {
+            (Some(i), Some(t)) => (i, t),
+            _ => {
+                self.log.push("Nothing is equipped in that slot", rltk::GREY);
+                return false;
+            }
+        };
 
-                let player_id = self.ecs.get_player_id();
+        let player_id = self.ecs.get_player_id();
+        let player_pos = self.ecs.get_player().pos();
+        let tiles = targeting::affected_tiles(self, player_pos, targeting, (dx, dy));
+        if tiles.is_empty() {
+            self.log.push("That is out of reach", rltk::GREY);
+            return false;
+        }
 
-                let player_container = self.ecs.get::<Container>(player_id).unwrap();
+        let targets = Targets::on_tiles(self, tiles);
+        if let Some(cost) = items::use_item(self, player_id, item_idx, &targets) {
+            self.action_cost = cost;
+        }
+        true
+    }
 
-                let eff_chain_valid = { 
-                    let player_equips = self.ecs.get::<Equipment>(player_id).unwrap();
+    // The closest living monster the player can see, where the targeting cursor starts
+    fn nearest_visible_enemy(&self) -> Option<(i32, i32)> {
+        let player_pos = self.ecs.get_player().pos();
+        self.ecs.query::<(&BasicEntity, &StatBlock)>()
+            .without::<Player>()
+            .iter()
+            .filter(|(_, (b, s))| !s.dead && self.map_state.is_visible(b.get_x(), b.get_y()))
+            .map(|(e, (b, _))| (b.pos(), e))
+            .min_by_key(|((x, y), e)| {
+                let (dx, dy) = (x - player_pos.0, y - player_pos.1);
+                (dx * dx + dy * dy, e.to_bits())
+            })
+            .map(|(pos, _)| pos)
+    }
 
-                    player_equips.equips
-                        .get(slot)
-                        .and_then(|x|
-                            x.and_then(|x| if player_container.items.get(x).is_some() { Some(x) } else { None } )
-                        )
-                        //.and_then(|a| a.effect_chain.as_ref().and_then(|z| Some(z)))
-                };
+    /*
+     * While aiming with the cursor movement keys move it and Enter, the slot's key or a click use the item,
+     * anything bound to Cancel stops aiming.
+     */
+    fn handle_cursor_input(&mut self, input: &InputFrame) {
+        let (slot, cursor) = match (self.directional_callback, self.targeting_cursor) {
+            (Some(slot), Some(cursor)) => (slot, cursor),
+            _ => {
+                self.targeting_cursor = None;
+                return;
+            }
+        };
 
-                if let Some(eff_chain_idx) = eff_chain_valid {
-                    let item_ref = player_container.items.get(eff_chain_idx).unwrap();
-                    if let Some(eff_chain) = item_ref.effect_chain.clone().as_ref() {
-                        drop(player_container);
-                        eff_chain.handle_effect(&mut self.ecs, player_id, found_entity.iter().map(|z| *z).collect());
-                    }
-                }
+        let mut fire_at = input.click.map(|click| self.camera.borrow().untransform_point(click));
+
+        if let Some(key) = input.key {
+            let command = self.keys.command_for(key);
+            if key == VirtualKeyCode::Return || key == VirtualKeyCode::NumpadEnter || command == Some(Command::UseSlot(slot)) {
+                fire_at = Some(cursor);
+            } else if key == VirtualKeyCode::Escape || command == Some(Command::Cancel) {
+                self.targeting_cursor = None;
+                self.directional_callback = None;
+            } else if let Some((dx, dy)) = command.and_then(|c| c.direction()) {
+                let x = math_utils::clamp(cursor.0 + dx, 0, self.map_width() - 1);
+                let y = math_utils::clamp(cursor.1 + dy, 0, self.map_height() - 1);
+                self.targeting_cursor = Some((x, y));
             }
-            self.directional_callback = None;
         }
 
-        return true;
+        if let Some((x, y)) = fire_at {
+            self.targeting_cursor = None;
+            self.directional_callback = None;
+            let (px, py) = self.ecs.get_player().pos();
+            self.perform(Action::UseEquipped(slot, (x - px, y - py)));
+        }
     }
 
-    fn handle_movement_input(&mut self, key: VirtualKeyCode, do_tick: &mut bool) {
-
-        if enumerate_keys().contains(&key) {
-            self.waiting_for_directional_input = true;
-            *do_tick = false;
-            self.directional_callback = Some(get_index_from_key(key).expect("This should never run."));
+    /*
+     * Carries out the player's action and runs the world until the player may act again,
+     * then takes any portal the player ended up on. Needs no window.
+     * Actions that turn out to be impossible, like picking up nothing, don't take the turn.
+     */
+    pub fn perform(&mut self, action: Action) {
+        let player_id = self.ecs.get_player_id();
+        let acted = match action {
+            Action::Move(dx, dy) => {
+                self.move_player_by(dx, dy);
+                true
+            }
+            Action::UseEquipped(slot, (dx, dy)) => self.use_equipped(slot, dx, dy),
+            Action::Wait => true,
+            Action::PickUp => items::pick_up(self, player_id),
+            Action::Drop(index) => items::drop_item(self, player_id, index),
+            Action::UseItem(index) => match items::use_on_self(self, player_id, index) {
+                Some(cost) => {
+                    self.action_cost = cost;
+                    true
+                }
+                None => false,
+            },
+        };
+        if !acted {
             return;
         }
 
-        match key {
-            VirtualKeyCode::S => self.save_player(),
+        self.on_turn();
 
-            VirtualKeyCode::Up => self.move_player_by(0, -1),
-            VirtualKeyCode::Down => self.move_player_by(0, 1),
-            VirtualKeyCode::Left => self.move_player_by(-1, 0),
-            VirtualKeyCode::Right => self.move_player_by(1, 0),
+        let destination = *self.destination_next_tick.borrow();
+        if let Some((destination, x, y)) = destination {
+            self.load_map_by_destination(destination, x, y);
+        }
+    }
 
-            VirtualKeyCode::Numpad8 => self.move_player_by(0, -1),
-            VirtualKeyCode::Numpad2 => self.move_player_by(0, 1),
-            VirtualKeyCode::Numpad4 => self.move_player_by(-1, 0),
-            VirtualKeyCode::Numpad6 => self.move_player_by(1, 0),
+    fn handle_directional_input(&mut self, key: VirtualKeyCode) {
+        let slot = self.directional_callback.take();
+        let dir = self.keys.command_for(key).and_then(|c| c.direction());
+        if let (Some(slot), Some(dir)) = (slot, dir) {
+            self.perform(Action::UseEquipped(slot, dir));
+        }
+    }
 
-            VirtualKeyCode::Numpad7 => self.move_player_by(-1, -1),
-            VirtualKeyCode::Numpad9 => self.move_player_by(1, -1),
-            VirtualKeyCode::Numpad1 => self.move_player_by(-1, 1),
-            VirtualKeyCode::Numpad3 => self.move_player_by(1, 1),
+    fn handle_command(&mut self, command: Command) {
+        match command {
+            Command::UseSlot(slot) => match self.equipped_targeting(slot) {
+                None => self.log.push("Nothing is equipped in that slot", rltk::GREY),
+                Some(targeting) if targeting.needs_cursor() => {
+                    self.directional_callback = Some(slot);
+                    self.targeting_cursor = Some(self.nearest_visible_enemy().unwrap_or_else(|| self.ecs.get_player().pos()));
+                    self.log.push("Aim with the movement keys, Enter to use, Esc to cancel", rltk::GREY);
+                }
+                Some(_) => {
+                    self.waiting_for_directional_input = true;
+                    self.directional_callback = Some(slot);
+                }
+            },
+            Command::Wait => self.perform(Action::Wait),
+            Command::PickUp => self.perform(Action::PickUp),
+            Command::Inventory => {
+                self.open_window = Some(Box::new(InventoryUI {
+                    container_id: self.ecs.get_player_id(),
+                    pending_item: RefCell::new(None)
+                }));
+            }
+            Command::CharacterSheet => {
+                self.open_window = Some(Box::new(progression::CharacterSheetUI));
+            }
+            Command::MessageLog => {
+                self.open_window = Some(Box::new(MessageLogUI {
+                    scroll: Cell::new(0),
+                }));
+            }
+            Command::LogUp => self.log.scroll_up(),
+            Command::LogDown => self.log.scroll_down(),
+            Command::Save => self.save_game(),
+            Command::Keybindings => {
+                self.open_window = Some(Box::new(RebindUI::default()));
+            }
+            Command::Cancel => {
+                self.currently_viewed_art = None;
+                self.currently_viewed_stat_block = None;
+            }
             _ => {
-                *do_tick = false;
+                if let Some((dx, dy)) = command.direction() {
+                    self.perform(Action::Move(dx, dy));
+                }
             }
-        };
+        }
     }
 
-    fn on_turn(&mut self) {
-        //Copy ids out of query then run the system on them
-        let _zombie_ticks = self.ecs
-            .query::<&ZombieAI>()
+    fn update_viewsheds(&mut self) {
+        let _viewsheds = self.ecs
+            .query::<&Viewshed>()
             .into_iter()
             .map(|(e, _)| e)
             .collect::<Vec<_>>();
 
+        for e in _viewsheds {
+            Viewshed::on_turn(self, e);
+        }
+    }
+
+    /*
+     * Runs the world after the player acted: the player pays for the action,
+     * then time advances tick by tick, letting every other actor act whenever its energy allows,
+     * until the player may act again.
+     */
+    fn on_turn(&mut self) {
+        let cost = std::mem::replace(&mut self.action_cost, scheduler::ACTION_COST);
+        let player_id = self.ecs.get_player_id();
+        Initiative::spend(self, player_id, cost);
+
+        self.update_viewsheds();
+
+        loop {
+            self.run_until_ready(player_id);
+            // A stunned player sits their turn out and time keeps going
+            if !status::lose_turn(self, player_id) {
+                break;
+            }
+            Initiative::spend(self, player_id, scheduler::ACTION_COST);
+        }
+    }
+
+    fn run_until_ready(&mut self, player_id: EntityIndex) {
+        while !Initiative::ready(self, player_id) {
+            Initiative::tick_all(self);
+            self.game_ticks += 1;
+
+            //Copy ids out of query then run the system on them
+            let _ready_actors = self.ecs
+                .query::<(&Initiative, Option<&ZombieAI>)>()
+                .without::<Player>()
+                .into_iter()
+                .filter(|(_, (init, _))| init.is_ready())
+                .map(|(e, (_, zombie))| (e, zombie.is_some()))
+                .collect::<Vec<_>>();
+
+            for (e, is_zombie) in _ready_actors {
+                if !self.ecs.contains(e) { continue; }
+                if status::lose_turn(self, e) {
+                    // Stunned, the action is spent doing nothing
+                } else if is_zombie {
+                    ZombieAI::on_turn(self, e);
+                }
+                Initiative::spend(self, e, scheduler::ACTION_COST);
+                self.update_viewsheds();
+            }
+
+            if self.game_ticks.is_multiple_of(scheduler::TICKS_PER_TURN) {
+                self.on_world_turn();
+            }
+
+            self.handle_deaths();
+        }
+    }
+
+    // Systems that run once per normal speed turn regardless of who acts
+    fn on_world_turn(&mut self) {
         let _self_destructs = self.ecs
             .query::<&SelfDestructAI>()
             .into_iter()
             .map(|(e, _)| e)
             .collect::<Vec<_>>();
 
-
-        for e in _zombie_ticks {
-            ZombieAI::on_turn(self, e);
-        }
-
         for e in _self_destructs {
             SelfDestructAI::on_turn(self, e);
         }
 
+        let _afflicted = self.ecs
+            .query::<&StatusEffects>()
+            .into_iter()
+            .map(|(e, _)| e)
+            .collect::<Vec<_>>();
+
+        for e in _afflicted {
+            status::on_turn(self, e);
+        }
+
         PlayerAI::on_turn(self, self.ecs.get_player_id());
+    }
 
+    fn handle_deaths(&mut self) {
         let _dead_entities = self.ecs.query::<&StatBlock>().into_iter().filter(|( _, z )| {
             z.hp.get_total() <= 0
         }).map(|(e, _)| e).collect::<Vec<_>>();
 
         // handle deaths
         for e in _dead_entities {
-            if self.ecs.get::<StatBlock>(e).unwrap().dead { return; }
+            if self.ecs.get::<StatBlock>(e).unwrap().dead { continue; }
 
             self.ecs.get_mut::<StatBlock>(e).unwrap().dead = true;
 
-            self.ecs.insert_one(e, SelfDestructAI { turns_left: 10 }).expect("Failed to insert self destruct ai");
+            let msg = if self.ecs.get::<Player>(e).is_ok() {
+                "You die...".to_string()
+            } else {
+                format!("{} dies", gamelog::describe(self, e))
+            };
+            self.log.push(msg, rltk::ORANGE);
 
+            // Corpses don't keep burning
+            let _ = self.ecs.remove_one::<StatusEffects>(e);
             self.ecs.get_mut::<BasicEntity>(e).unwrap().d = Display {
                 glyph: rltk::to_cp437('%'),
                 fg: rltk::RED,
                 bg: rltk::BLACK
             };
 
-        }
+            // The player's corpse stays put, everything else would go looking for it
+            if self.ecs.get::<Player>(e).is_ok() {
+                self.game_over = Some(DeathSummary::from_state(self));
+                continue;
+            }
+
+            self.ecs.insert_one(e, SelfDestructAI { turns_left: 10 }).expect("Failed to insert self destruct ai");
 
+            // Monsters leave what they carried next to their corpse, then roll their loot table
+            progression::award_kill(self, e);
+            items::drop_all(self, e);
 
+            let table = self.ecs.get::<LootDrop>(e).ok()
+                .and_then(|l| self.loot_tables.get(&l.table).cloned());
+            if let Some(table) = table {
+                table.handle_loot(self, e);
+            }
+        }
+    }
+
+    // Remembers what hurt the player in case it turns out to be fatal
+    fn note_hit(&mut self, target: EntityIndex, source: String) {
+        if self.ecs.get::<Player>(target).is_ok() {
+            self.player_last_hit_by = Some(source);
+        }
     }
 
     #[allow(dead_code)]
-    fn generate_map(width: i32, height: i32) -> Vec<Cell<TileType>> {
+    fn generate_map(rng: &mut GameRng, width: i32, height: i32) -> Vec<Cell<TileType>> {
         let mut map = vec![];
         for _ in 0..width * height {
-            if math_utils::chance(0.03) {
+            if rng.chance(0.03) {
                 map.push(Cell::new(TileType::Wall(Display {
                     glyph: '^' as u16,
                     fg: rltk::GREEN,
@@ -246,16 +488,16 @@ impl State {
     #[allow(dead_code)]
     fn generate_entities(&mut self) {
         for _ in 0..(self.map_width() * self.map_height()) {
-            if !math_utils::chance(0.01) {
+            if !self.rng.chance(0.01) {
                 continue;
             }
 
-            let pos = math_utils::random_point(1, self.map_width() - 1, 1, self.map_height() - 1);
+            let pos = self.rng.random_point(1, self.map_width() - 1, 1, self.map_height() - 1);
 
-            if !math_utils::chance(0.3) {
-                entity_create::create_goblin(self, pos);
+            if !self.rng.chance(0.3) {
+                entity_create::resolve_entity_string(self, pos, "Goblin");
             } else {
-                entity_create::create_fire_elemental(self, pos);
+                entity_create::resolve_entity_string(self, pos, "SFElemental");
             }
         }
     }
@@ -268,82 +510,116 @@ impl State {
             }
             let (x, y) = state.map_state.idx_xy(load_entity.0);
             let m_entity = load_entity.1.as_ref().unwrap();
-            let e_indx = entity_create::resolve_entity_string(state, (x, y), m_entity.name.as_str());
-            state.map_state.strict_add_from_pos((x, y), e_indx);
+            match entity_create::resolve_entity_string(state, (x, y), m_entity.name.as_str()) {
+                Some(e_indx) => state.map_state.strict_add_from_pos((x, y), e_indx),
+                None => eprintln!("Unknown entity {} at {} {}, skipping", m_entity.name, x, y),
+            }
         }
     }
 
-    fn new() -> State {
-        let player = BasicEntity {
-            x: 1,
-            y: 1,
-            d: Display {
-                glyph: '@' as u16,
-                fg: rltk::YELLOW,
-                bg: rltk::BLACK,
-            },
-        };
-
-        let f_c_dir = std::env::current_dir().unwrap();
-
-        let arc_load = |s: &'static str| {
-            Arc::new(
-                XpFile::read(
-                    &mut File::open(f_c_dir.join(s)).expect(
-                        format!(
-                            "Could not find the file: {}",
-                            f_c_dir.join(s).as_path().to_str().unwrap()
-                        )
-                        .as_str(),
-                    ),
-                )
-                .unwrap(),
-            )
-        };
-
-        let load_map = map_utils::load_from_file("main.map");
-
-        let mut state = State {
+    fn with_map_state(map_state: InternalMapState, save_slot: usize, rng: GameRng) -> State {
+        let assets = AssetManager::load_manifest("assets.json").unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            AssetManager::new(Default::default())
+        });
+
+        let monsters = MonsterRegistry::load_from_file("monsters.json").unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            MonsterRegistry::default()
+        });
+        let items = ItemRegistry::load_from_file("items.json").unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            ItemRegistry::default()
+        });
+
+        State {
             ecs: EntityWorld { ecs: World::new() },
 
-            map_state: InternalMapState {
-                map_width: load_map.width,
-                map_height: load_map.height,
+            map_state,
 
-                tiles: map_utils::map_to_cells(load_map.tiles),
-                portal_locations: vec![
-                    "main.map",
-                    "portal_land.map"
-                ],
-                entities: vec![],
-            },
+            assets,
 
-            resources: vec![
-                arc_load("dude.png.xp"), //0
-                arc_load("firelemental.xp"), //1
-                arc_load("guard.xp"),  //2
-                arc_load("player.xp"), //3
-                arc_load("spider.xp"), //4
-                arc_load("kingspider.xp"), //5
-                arc_load("rockdude.xp"), //6
-                arc_load("tall dude.xp"), //7
-                arc_load("crazyeyes.xp"), //8
-            ],
+            monsters,
+            items,
+            loot_tables: LootRegistry::load_from_file("loot_tables.json"),
             queued_destruction: RefCell::new(vec![]),
 
             camera: RefCell::new(Camera::new(-20, -20)),
 
             waiting_for_directional_input: false,
             directional_callback: None,
+            targeting_cursor: None,
             currently_viewed_art: None,
             currently_viewed_stat_block: None,
 
             until_player_save: 10.0,
+            save_slot,
 
             destination_next_tick: RefCell::new(None),
             open_window: None,
-        };
 
+            log: GameLog::default(),
+            rng,
+            keys: Keybindings::load_from_file(keybindings::KEYBINDINGS_FILE),
+
+            recorder: None,
+            replay: None,
+
+            action_cost: scheduler::ACTION_COST,
+            game_ticks: 0,
+
+            player_last_hit_by: None,
+            game_over: None,
+        }
+    }
+
+    fn load_game(slot: usize) -> Result<State, String> {
+        Self::from_save(SaveGame::read_from_slot(slot)?, slot)
+    }
+
+    fn from_save(save: SaveGame, slot: usize) -> Result<State, String> {
+        let mut state = Self::with_map_state(InternalMapState {
+            map_width: save.map_width,
+            map_height: save.map_height,
+
+            current_map: save.current_map,
+            portal_locations: save.portal_locations,
+
+            tiles: map_utils::map_to_cells(save.tiles),
+            entities: vec![],
+            revealed: vec![],
+            visible: vec![],
+        }, slot, save.rng);
+        state.game_ticks = save.game_ticks;
+
+        state.log.push(format!("Seed {}", state.rng.seed()), rltk::GREY);
+
+        state.map_state.clear_entities();
+        state.map_state.clear_visibility();
+        if save.revealed.len() == state.map_state.revealed.len() {
+            state.map_state.revealed = save.revealed;
+        }
+
+        for saved in save.entities {
+            let e = saved.spawn(&mut state.ecs);
+            let pos = state.ecs.get::<BasicEntity>(e).map(|b| b.pos());
+            if let Ok(pos) = pos {
+                state.map_state.strict_add_from_pos(pos, e);
+            }
+        }
+
+        if state.ecs.query::<&Player>().iter().next().is_none() {
+            return Err(format!("Save slot {} has no player", slot));
+        }
+
+        let (px, py) = state.ecs.get_player().pos();
+        state.camera.borrow_mut().update_xy(px, py);
+        state.update_viewsheds();
+
+        Ok(state)
+    }
+
+    fn new(save_slot: usize, seed: Option<u64>, destinations: Vec<MapSource>) -> State {
         let player_stat_block: StatBlock = {
             if Path::new("player.json").exists() {
                 let mut file = File::open("player.json").unwrap();
@@ -360,35 +636,101 @@ impl State {
             }
         };
 
+        Self::start_new_game(
+            save_slot,
+            seed.map_or_else(GameRng::from_entropy, GameRng::seeded),
+            destinations,
+            player_stat_block,
+        )
+    }
+
+    fn from_replay(replay: Replay) -> State {
+        let mut state = Self::start_new_game(0, GameRng::seeded(replay.seed), replay.destinations, replay.player_stats);
+        if let Some(bindings) = replay.keybindings {
+            state.keys = Keybindings::new(bindings);
+        }
+        state.replay = Some(ReplayPlayer::new(replay.inputs));
+        state
+    }
+
+    // Records every input from here on, only meaningful right after starting a new game
+    fn start_recording(&mut self, file_name: &str) {
+        let player_stats = self.get_player_stat_block().clone();
+        self.recorder = Some(InputRecorder::new(
+            file_name,
+            self.rng.seed(),
+            self.map_state.portal_locations.clone(),
+            player_stats,
+            self.keys.bindings().to_vec(),
+        ));
+    }
+
+    fn start_new_game(save_slot: usize, rng: GameRng, destinations: Vec<MapSource>, player_stat_block: StatBlock) -> State {
+        let player = BasicEntity {
+            x: 1,
+            y: 1,
+            d: Display {
+                glyph: '@' as u16,
+                fg: rltk::YELLOW,
+                bg: rltk::BLACK,
+            },
+        };
+
+        let mut state = Self::with_map_state(InternalMapState {
+            map_width: 0,
+            map_height: 0,
+
+            current_map: 0,
+            portal_locations: destinations,
+
+            tiles: vec![],
+            entities: vec![],
+            revealed: vec![],
+            visible: vec![],
+        }, save_slot, rng);
+
+        state.log.push(format!("Seed {}", state.rng.seed()), rltk::GREY);
+
+        let load_map = state.map_state.portal_locations[0]
+            .build(&state.monsters, state.rng.rng());
+        let player = BasicEntity {
+            x: load_map.player_start.map_or(player.x, |p| p.0),
+            y: load_map.player_start.map_or(player.y, |p| p.1),
+            ..player
+        };
+        state.map_state.map_width = load_map.width;
+        state.map_state.map_height = load_map.height;
+        state.map_state.tiles = map_utils::map_to_cells(load_map.tiles);
+
         let player_pos = player.pos();
+        // Everyone starts out with the sword from the item raws
+        let starting_items = state.items.get("Rusty Sword").map(|raw| raw.to_item()).into_iter().collect();
         let player_entity_id = state.ecs.spawn((
         Container {
-            items: vec![Item {
-                name: "Rusty Sword".to_string(),
-                art: state.resources[0].clone(),
-                // Gotta reference count this bs because callbacks are dumb in rust fml otherwise
-                // deal with the wrath of the dyanmic size clone trait hate
-                effect_chain: Arc::new(Some(Box::new(SingleTargetEffects::DamageTarget(None, 5)))),
-            }],
+            items: starting_items,
             max_items: 999,
         }, 
-        Equipment {
-            equips: vec![None, None, None],
-            max_equips: 3,
-        },
+        Equipment::new(),
         Player,
         player,
         PlayerAI,
         player_stat_block,
+        Viewshed::new(8),
+        Initiative::new(scheduler::NORMAL_SPEED),
         EntityView {
                 name: "Me...".to_string(),
-                art: state.resources[3].clone(),
+                art: "player".to_string(),
             }
         ));
+        // Bonuses in player.json belonged to the last game's equipment
+        items::recompute_bonuses(&mut state, player_entity_id);
 
         //This clears entities so
         Self::load_entities_from_map(&mut state, &load_map.entities);
         state.map_state.strict_add_from_pos(player_pos, player_entity_id);
+        state.map_state.clear_visibility();
+        state.update_viewsheds();
+        state.camera.borrow_mut().update_xy(player_pos.0, player_pos.1);
 
         // state.generate_entities();
 
@@ -420,8 +762,12 @@ impl State {
 
         println!("{} {}", x, y);
 
-        let x_map = self.map_state.portal_locations[destination];
-        let load_map = map_utils::load_from_file(x_map);
+        let load_map = self.map_state.portal_locations[destination]
+            .build(&self.monsters, self.rng.rng());
+        self.map_state.current_map = destination;
+
+        // Generated floors decide where the player arrives
+        let (x, y) = load_map.player_start.unwrap_or((x, y));
 
         self.map_state.map_width = load_map.width;
         self.map_state.map_height = load_map.height;
@@ -447,7 +793,11 @@ impl State {
         _p.set_y(y);
         let pos = self.map_state.xy_idx(x, y);
         self.map_state.entities[pos].insert(_pid);
-        
+        drop(_p);
+
+        self.map_state.clear_visibility();
+        self.ecs.get_mut::<Viewshed>(_pid).unwrap().dirty = true;
+        self.update_viewsheds();
     }
 
 
@@ -470,6 +820,10 @@ impl State {
             let entity_r_pos = entity_r.pos();
             //Should return true for add
             assert!(self.map_state.set_entity_from_pos(entity_r_pos, entity), "Desynced entity position");
+            drop(entity_r);
+            if let Ok(mut viewshed) = self.ecs.get_mut::<Viewshed>(entity) {
+                viewshed.dirty = true;
+            }
             return (x, y);
         }
         return (0, 0);
@@ -490,6 +844,12 @@ impl State {
         if let TileType::Portal(_, destination, x, y) = self.map_state.tiles[idx_of].get() {
             *self.destination_next_tick.borrow_mut() = Some((destination, x, y));
         }
+        if deltas != (0, 0) {
+            if let Some(e) = items::floor_item_at(self, (x, y)) {
+                let msg = format!("You see a {} here", self.ecs.get::<FloorItem>(e).unwrap().item.name);
+                self.log.push(msg, rltk::GREY);
+            }
+        }
     }
 
     fn draw_map(&self, g_db: &mut DrawBatch) {
@@ -500,29 +860,46 @@ impl State {
 
         for x in l_x..h_x {
             for y in l_y..h_y {
+                if !self.map_state.is_revealed(x, y) {
+                    continue;
+                }
                 let tile_idx_local = self.map_state.xy_idx(x, y);
                 let tile = self.map_state.tiles[tile_idx_local].get();
+                let visible = self.map_state.is_visible(x, y);
                 let (x, y) = self.camera.borrow().transform_point((x, y));
 
-                match tile {
-                    TileType::Floor(d) => {
-                        g_db.set(Point::new(x, y), ColorPair::new(d.fg, d.bg), d.glyph);
-                    }
-                    TileType::Wall(d) => {
-                        g_db.set(Point::new(x, y), ColorPair::new(d.fg, d.bg), d.glyph);
-                    }
-                    TileType::Portal(d, _, _, _) => {
-                        g_db.set(Point::new(x, y), ColorPair::new(d.fg, d.bg), d.glyph);
-                    }
+                let d = match tile {
+                    TileType::Floor(d) => d,
+                    TileType::Wall(d) => d,
+                    TileType::Portal(d, _, _, _) => d,
+                };
+
+                // Remembered but unseen tiles are drawn dimmed
+                if visible {
+                    g_db.set(Point::new(x, y), ColorPair::new(d.fg, d.bg), d.glyph);
+                } else {
+                    g_db.set(
+                        Point::new(x, y),
+                        ColorPair::new(d.get_fg().to_greyscale() * 0.5, d.get_bg() * 0.5),
+                        d.glyph,
+                    );
                 }
             }
         }
 
-        for entity in self.ecs.query::<&BasicEntity>().iter().map(|( _, p )| p) {
+        // Floor items first so whatever stands on them is drawn over them
+        let mut floor_items = self.ecs.query::<&BasicEntity>().with::<FloorItem>();
+        let mut standing = self.ecs.query::<&BasicEntity>().without::<FloorItem>();
+        let entities = floor_items.iter().chain(standing.iter());
+
+        for entity in entities.map(|( _, p )| p) {
             let (x, y) = (entity.get_x(), entity.get_y());
             if !(l_x..h_x).contains(&x) || !(l_y..h_y).contains(&y) {
                 continue;
             }
+            if !self.map_state.is_visible(x, y) {
+                continue;
+            }
             let (x, y) = self.camera.borrow().transform_point((x, y));
             g_db.set(
                 Point::new(x, y),
@@ -559,6 +936,24 @@ impl State {
                 DRW_ARR(x - 1, y, '←', g_db);
             }
         }
+
+        //Highlight what the targeting cursor would hit
+        if let (Some(slot), Some(cursor)) = (self.directional_callback, self.targeting_cursor) {
+            let player_pos = self.ecs.get_player().pos();
+            let offset = (cursor.0 - player_pos.0, cursor.1 - player_pos.1);
+            let tiles = self.equipped_targeting(slot)
+                .map(|t| targeting::affected_tiles(self, player_pos, t, offset))
+                .unwrap_or_default();
+
+            for tile in &tiles {
+                let (x, y) = self.camera.borrow().transform_point(*tile);
+                g_db.set_bg(Point::new(x, y), RGB::from_u8(128, 64, 0));
+            }
+
+            let cursor_bg = if tiles.is_empty() { rltk::RED } else { rltk::YELLOW };
+            let (x, y) = self.camera.borrow().transform_point(cursor);
+            g_db.set_bg(Point::new(x, y), RGB::named(cursor_bg));
+        }
     }
 
     fn get_player_stat_block(&self) -> RefMut<StatBlock> {
@@ -571,25 +966,27 @@ impl State {
         file.write(string_buf.as_bytes()).expect("Failed to write to player.json");
     }
 
-    fn update(&mut self, ctx: &mut Rltk) {
-        let destination_tick_info = {
-            if let Some((destination, x, y)) = *self.destination_next_tick.borrow_mut() {
-                Some((destination, x, y))
-            } else {
-                None
-            }
-        };
-
-        if let Some((destination, x, y)) =  destination_tick_info {
-            self.load_map_by_destination(destination, x, y)
+    fn save_game(&mut self) {
+        if self.replay.is_some() {
+            return;
+        }
+        let save = SaveGame::from_state(self);
+        self.save_player();
+        if let Err(e) = save.write_to_slot(self.save_slot) {
+            eprintln!("Failed to save to slot {}: {}", self.save_slot, e);
         }
+    }
 
+    fn update(&mut self, ctx: &mut Rltk) {
         self.camera.borrow_mut().tween_tick(ctx.frame_time_ms);
 
+        // Saving leaves the rng alone, so autosaves don't change how a recorded run plays out
+        let autosave = self.replay.is_none();
+
         self.until_player_save -= ctx.frame_time_ms / 1000.0;
-        if self.until_player_save <= 0.0 {
+        if autosave && self.until_player_save <= 0.0 {
             self.until_player_save = 30.0;
-            self.save_player();
+            self.save_game();
         }
     }
 
@@ -631,17 +1028,57 @@ impl State {
             }
 
             stat_block_to_draw.make_text_builder(&mut tb);
-            let mut tblock = TextBlock::new(41, 0, 40, 20);
+            if let Ok(status) = self.ecs.get::<StatusEffects>(e_id) {
+                tb.ln();
+                status.make_text_builder(&mut tb);
+            }
+            let mut tblock = TextBlock::new(41, 0, 39, 10);
             tblock
                 .print(&tb)
                 .expect("Too much text for stat block to render");
             tblock.render_to_draw_batch(&mut g_db);
         }
 
-        if ctx.left_click {
-            let (x, y) = ctx.mouse_pos();
+        //Render the message log under the stat block
+        {
+            for x in 41..80 {
+                g_db.set(
+                    Point::new(x, 10),
+                    ColorPair::new(RGB::named(rltk::WHITE), RGB::named(rltk::BLACK)),
+                    rltk::to_cp437('-'),
+                );
+            }
+            self.log.render_panel(&mut g_db, 41, 11, 39, 9);
+        }
+
+        self.draw_map(&mut g_db);
+
+        g_db.submit(0).expect("Rendering error with draw batch");
+
+        rltk::render_draw_buffer(ctx).expect("Rendering error");
+
+        let _player_art = self
+            .ecs
+            .query::<(&Player, &EntityView)>()
+            .iter().map(|(_, (_, c))| { c }).next().unwrap();
+
+        let c_view_art = self.ecs.get::<EntityView>(self.currently_viewed_art.unwrap_or(self.ecs.get_player_id()))
+            .map(|v| v.art.clone());
+        if c_view_art.is_err() {
+            self.currently_viewed_art = None;
+        }
+        let art = c_view_art.unwrap_or_else(|_| self.ecs.get_player_view().art.clone());
+        self.print_image_at(41, 20, &art, ctx);
+    }
+
+    // Clicking a visible entity shows its stats and art, keys go through the keybindings
+    fn handle_game_input(&mut self, input: &InputFrame) {
+        if let Some((x, y)) = input.click {
             for (e_id, entity) in self.ecs.query::<&BasicEntity>().iter() {
                 let (ex, ey) = (entity.get_x(), entity.get_y());
+                if !self.map_state.is_visible(ex, ey) {
+                    continue;
+                }
                 let (ex, ey) = self.camera.borrow().transform_point((ex, ey));
                 if ex == x && ey == y {
                     let view = self.ecs.get::<EntityView>(e_id);
@@ -658,54 +1095,42 @@ impl State {
             }
         }
 
-        //Handle keyboard input WASD movements using self.move_player
-        match ctx.key {
-            None => {}
-            Some(key) => {
-                let mut do_tick = true;
-
-                if let Some(key) = ctx.key {
-                    if key == VirtualKeyCode::Escape {
-                        self.currently_viewed_art = None;
-                        self.currently_viewed_stat_block = None;
-                    }
-                }
-
-                if self.waiting_for_directional_input {
-                    //Do the directional input callback here
-                    self.handle_directional_input(key);
-                    self.waiting_for_directional_input = false;
-                } else {
-                    if key == VirtualKeyCode::I {
-                        self.open_window = Some(Box::new(InventoryUI {
-                            container_id: self.ecs.get_player_id(),
-                            pending_item: RefCell::new(None)
-                        }));
-                    }
-                    self.handle_movement_input(key, &mut do_tick);
-                }
-                if do_tick {
-                    self.on_turn();
-                }
+        //Handle keyboard input through the keybindings
+        if self.targeting_cursor.is_some() {
+            self.handle_cursor_input(input);
+        } else if let Some(key) = input.key {
+            if self.waiting_for_directional_input {
+                //Do the directional input callback here
+                self.handle_directional_input(key);
+                self.waiting_for_directional_input = false;
+            } else if let Some(command) = self.keys.command_for(key) {
+                self.handle_command(command);
             }
         }
+    }
 
-        self.draw_map(&mut g_db);
-
-        g_db.submit(0).expect("Rendering error with draw batch");
+    /*
+     * Feeds one frame of input to the open window or else the game, recording it if a recording is running.
+     * Draws nothing, so a replay can be run through it without a window.
+     */
+    pub fn step(&mut self, input: &InputFrame) {
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.record(input);
+        }
 
-        rltk::render_draw_buffer(ctx).expect("Rendering error");
+        if self.open_window.is_some() {
+            let bo_w = std::mem::take(&mut self.open_window);
 
-        let _player_art = self
-            .ecs
-            .query::<(&Player, &EntityView)>()
-            .iter().map(|(_, (_, c))| { c }).next().unwrap();
+            let close = bo_w.as_ref()
+                .unwrap()
+                .on_input(self, input.key);
 
-        let c_view_art = self.ecs.get::<EntityView>(self.currently_viewed_art.unwrap_or(self.ecs.get_player_id()));
-        if c_view_art.is_err() {
-            self.currently_viewed_art = None;
+            if !close {
+                let _ = std::mem::replace(&mut self.open_window, bo_w);
+            }
+        } else {
+            self.handle_game_input(input);
         }
-        self.print_image_at(41, 20, &c_view_art.unwrap_or(self.ecs.get_player_view()), ctx);
     }
 
     fn map_width(&self) -> i32 {
@@ -720,39 +1145,52 @@ impl State {
 
 impl GameState for State {
     fn tick(&mut self, ctx: &mut Rltk) {
-        if self.open_window.is_some() {
-            let bo_w = std::mem::take(&mut self.open_window);
+        let input = match self.replay.as_mut() {
+            Some(replay) => replay.next_input(ctx.frame_time_ms),
+            None => InputFrame::from_ctx(ctx),
+        };
 
-            let close = bo_w.as_ref()
-                .unwrap()
-                .on_input(self, ctx.key);
+        self.step(&input);
 
+        if self.open_window.is_some() {
+            let bo_w = std::mem::take(&mut self.open_window);
             bo_w.as_ref().unwrap().render(ctx, self);
-            if !close {
-                let _ = std::mem::replace(&mut self.open_window, bo_w);
-            }
+            let _ = std::mem::replace(&mut self.open_window, bo_w);
         } else {
             self.update(ctx);
             self.render_game_window(ctx);
         }
+
+        if self.replay.as_ref().is_some_and(|r| r.is_finished()) {
+            self.replay = None;
+            self.log.push("Replay finished", rltk::YELLOW);
+        }
     }
 
 }
 
 fn main() -> BResult<()> {
-    let context = BTermBuilder::simple(80, 40).unwrap().build()?;
-
-    //Ask the user for a number and then get it from stdin
-    let mut gametype = String::new();
-    println!("Type 0 for normal game 1 for map editor");
-    std::io::stdin().read_line(&mut gametype)?;
-    let gametype = gametype.trim().parse::<i32>().unwrap();
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let options = match LaunchOptions::parse(&args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, launcher::USAGE);
+            // The usual exit code for bad usage, so scripts can tell
+            std::process::exit(2);
+        }
+    };
+    if options.help {
+        println!("{}", launcher::USAGE);
+        return Ok(());
+    }
 
-    if gametype == 0 {
-        let gs = State::new();
-        rltk::main_loop(context, gs)
-    } else {
-        let gs = MapEditorState::new(32, 32);
-        rltk::main_loop(context, gs)
+    let mut builder = BTermBuilder::simple(launcher::CONSOLE_WIDTH, launcher::CONSOLE_HEIGHT)?
+        .with_title("rust-rltk")
+        .with_fullscreen(options.fullscreen);
+    if let Some((w, h)) = options.tile_dimensions() {
+        builder = builder.with_tile_dimensions(w, h);
     }
+    let context = builder.build()?;
+
+    rltk::main_loop(context, Launcher::new(options))
 }
@@ -1,17 +1,43 @@
-use std::{fs::File, io::{stdin, stdout}};
-use std::io::Write;
+use std::path::Path;
 
 use rltk::{Rltk, GameState, VirtualKeyCode};
 use serde::{Deserialize, Serialize};
-use crate::structs::{map_utils::MapDescriptor, Display, TileType, self};
+use crate::structs::{map_utils::MapDescriptor, Display, TileType};
+use crate::raws::{ItemRegistry, MonsterRegistry};
+use crate::edit_history::{CellEdit, EditHistory};
+use crate::editor_palette::{self, Palette};
+use crate::editor_tools::{self, Tool};
+use crate::editor_files::{self, DialogEvent, FileDialog, PortalShift};
+use crate::editor_prompt::{Prompt, PromptEvent};
+
+// A shape being dragged out, from where the button went down
+#[derive(Copy, Clone)]
+struct Drag {
+    start: (i32, i32),
+    // Right button shapes paint walls
+    wall: bool,
+}
 
 //Describes an entity in the map editor
-#[derive(Clone, Serialize, Deserialize)]
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
 pub struct MEEntity {
     pub d: Display,
     pub name: String,
 }
 
+// The part of the window the map shows in, the palette is right of it and the tool bar and status line below.
+// Bigger maps scroll through it with the arrow keys
+pub const MAP_VIEW_WIDTH: i32 = editor_palette::PANEL_X - 1;
+pub const MAP_VIEW_HEIGHT: i32 = crate::launcher::CONSOLE_HEIGHT as i32 - 2;
+
+fn blank_tile() -> TileType {
+    TileType::Floor(Display {
+        glyph: '.' as u16,
+        fg: rltk::WHITE,
+        bg: rltk::BLACK
+    })
+}
+
 pub struct MapEditorState {
     width: i32,
     height: i32,
@@ -20,29 +46,144 @@ pub struct MapEditorState {
     entities: Vec<Option<MEEntity>>,
     picked_tile: Display,
     picked_entity: Option<MEEntity>,
+
+    monsters: MonsterRegistry,
+    items: ItemRegistry,
+
+    // Where S saves to and L loads from, None until a new map is first saved
+    file_name: Option<String>,
+    // Kept as loaded, the editor has no way to place it
+    player_start: Option<(i32, i32)>,
+    // How far resizing moved the map since it was last saved, portals into it from other maps move by this on save
+    portal_shift: PortalShift,
+    dialog: Option<FileDialog>,
+    prompt: Option<Prompt>,
+    // The outcome of the last save or load, shown until the next key press
+    notice: Option<String>,
+
+    history: EditHistory,
+
+    palette: Palette,
+    tool: Tool,
+    drag: Option<Drag>,
+    // Right button state last frame, the palette acts when it goes down
+    right_was_held: bool,
+    // Map position shown in the top left corner of the view
+    camera: (i32, i32),
 }
 
 impl MapEditorState {
     pub fn new(width: i32, height: i32) -> Self {
+        let monsters = MonsterRegistry::load_from_file("monsters.json").unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            MonsterRegistry::default()
+        });
+        let items = ItemRegistry::load_from_file("items.json").unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            ItemRegistry::default()
+        });
+
         MapEditorState {
             width,
             height,
-            map_tiles: vec![
-                TileType::Floor(Display {
-                    glyph: '.' as u16,
-                    fg: rltk::WHITE,
-                    bg: rltk::BLACK
-                });
-                (width * height) as usize
-            ],
+            map_tiles: vec![blank_tile(); (width * height) as usize],
             entities: vec![None; (width * height) as usize],
             picked_entity: None,
+            monsters,
+            items,
             picked_tile: Display {
                 glyph: '.' as u16,
                 fg: rltk::WHITE,
                 bg: rltk::BLACK,
             },
+            file_name: None,
+            player_start: None,
+            portal_shift: PortalShift::default(),
+            dialog: None,
+            prompt: None,
+            notice: None,
+            history: EditHistory::default(),
+            palette: Palette::new(),
+            tool: Tool::Brush,
+            drag: None,
+            right_was_held: false,
+            camera: (0, 0),
+        }
+    }
+
+    // Starts on a blank map with the list of maps to open up
+    pub fn browse() -> Self {
+        let mut editor = Self::new(32, 32);
+        editor.dialog = Some(FileDialog::open(false));
+        editor
+    }
+
+    // Edits file_name, starting from a blank 32x32 map if it doesn't exist yet
+    pub fn open(file_name: &str) -> Result<Self, String> {
+        let mut editor = Self::new(32, 32);
+        editor.file_name = Some(file_name.to_string());
+        if Path::new(file_name).exists() {
+            editor.load_from_file(file_name)?;
         }
+        Ok(editor)
+    }
+
+    fn load_from_file(&mut self, file_name: &str) -> Result<(), String> {
+        let md = editor_files::read_map(file_name)?;
+        self.map_tiles = md.tiles;
+        self.width = md.width;
+        self.height = md.height;
+        self.entities = md.entities;
+        self.player_start = md.player_start;
+        self.file_name = Some(file_name.to_string());
+        self.portal_shift.clear();
+        self.drag = None;
+        self.camera = (0, 0);
+        self.history.clear();
+        Ok(())
+    }
+
+    fn new_map(&mut self, width: i32, height: i32) {
+        self.width = width;
+        self.height = height;
+        self.map_tiles = vec![blank_tile(); (width * height) as usize];
+        self.entities = vec![None; (width * height) as usize];
+        self.player_start = None;
+        self.file_name = None;
+        self.portal_shift.clear();
+        self.drag = None;
+        self.camera = (0, 0);
+        self.history.clear();
+    }
+
+    fn descriptor(&self) -> MapDescriptor {
+        MapDescriptor {
+            tiles: self.map_tiles.clone(),
+            width: self.width,
+            height: self.height,
+            entities: self.entities.clone(),
+            player_start: self.player_start,
+        }
+    }
+
+    /*
+     * Resizes the map around anchor, see editor_files::resize. This can't be undone, so the history is dropped.
+     * Portals in this map that lead back into it move along with everything else.
+     */
+    fn resize(&mut self, width: i32, height: i32, anchor: (i32, i32)) {
+        let (mut md, shift) = editor_files::resize(&self.descriptor(), width, height, anchor, blank_tile());
+        if let Some(file_name) = &self.file_name {
+            editor_files::shift_portals(&mut md.tiles, &editor_files::destinations_of(file_name), shift);
+        }
+        self.width = md.width;
+        self.height = md.height;
+        self.map_tiles = md.tiles;
+        self.entities = md.entities;
+        self.player_start = md.player_start;
+        self.portal_shift.add(shift);
+        self.drag = None;
+        self.scroll(0, 0);
+        self.history.clear_unsaved();
     }
 
     fn xy_idx(&self, x: i32, y: i32) -> usize {
@@ -53,12 +194,48 @@ impl MapEditorState {
         pos.0 >= 0 && pos.0 < self.width && pos.1 >= 0 && pos.1 < self.height
     }
 
+    fn in_view(screen: (i32, i32)) -> bool {
+        screen.0 >= 0 && screen.0 < MAP_VIEW_WIDTH && screen.1 >= 0 && screen.1 < MAP_VIEW_HEIGHT
+    }
+
+    fn to_map(&self, screen: (i32, i32)) -> (i32, i32) {
+        (screen.0 + self.camera.0, screen.1 + self.camera.1)
+    }
+
+    fn to_screen(&self, pos: (i32, i32)) -> (i32, i32) {
+        (pos.0 - self.camera.0, pos.1 - self.camera.1)
+    }
+
+    // Moves the view, keeping as much of the map in it as fits
+    fn scroll(&mut self, dx: i32, dy: i32) {
+        let max = ((self.width - MAP_VIEW_WIDTH).max(0), (self.height - MAP_VIEW_HEIGHT).max(0));
+        self.camera = ((self.camera.0 + dx).clamp(0, max.0), (self.camera.1 + dy).clamp(0, max.1));
+    }
+
+    // Every change to the map goes through these two so it can be undone
+    fn set_tile(&mut self, idx: usize, tile: TileType) {
+        let before = self.map_tiles[idx];
+        if before == tile {
+            return;
+        }
+        self.map_tiles[idx] = tile;
+        self.history.record(CellEdit::Tile { idx, before, after: tile });
+    }
+
+    fn set_entity(&mut self, idx: usize, entity: Option<MEEntity>) {
+        if self.entities[idx] == entity {
+            return;
+        }
+        let before = std::mem::replace(&mut self.entities[idx], entity.clone());
+        self.history.record(CellEdit::Entity { idx, before, after: entity });
+    }
+
     fn handle_l_click(&mut self, pos: (i32, i32)) {
         if !self.in_bounds(pos) {
             return;
         }
         let indx = self.xy_idx(pos.0, pos.1);
-        self.map_tiles[indx] = TileType::Floor(self.picked_tile);
+        self.set_tile(indx, TileType::Floor(self.picked_tile));
     }
 
     fn handle_r_click(&mut self, pos: (i32, i32)) {
@@ -66,7 +243,65 @@ impl MapEditorState {
             return;
         }
         let indx = self.xy_idx(pos.0, pos.1);
-        self.map_tiles[indx] = TileType::Wall(self.picked_tile);
+        self.set_tile(indx, TileType::Wall(self.picked_tile));
+    }
+
+    fn paint_tile(&self, wall: bool) -> TileType {
+        if wall { TileType::Wall(self.picked_tile) } else { TileType::Floor(self.picked_tile) }
+    }
+
+    // Cells the flood fill would repaint from pos, every connected tile of the same kind as the one there
+    fn fill_region(&self, pos: (i32, i32)) -> Vec<usize> {
+        if !self.in_bounds(pos) {
+            return vec![];
+        }
+        let kind = std::mem::discriminant(&self.map_tiles[self.xy_idx(pos.0, pos.1)]);
+        editor_tools::flood_fill(self.width, self.height, pos, |i| std::mem::discriminant(&self.map_tiles[i]) == kind)
+    }
+
+    // Cells the current tool would paint if the button came up now, fills only show while the mouse is over the map
+    fn pending_cells(&self, mouse_pos: (i32, i32), over_map: bool) -> Vec<usize> {
+        match self.drag {
+            Some(drag) => self.tool.shape(drag.start, mouse_pos)
+                .into_iter()
+                .filter(|pos| self.in_bounds(*pos))
+                .map(|(x, y)| self.xy_idx(x, y))
+                .collect(),
+            None if self.tool == Tool::Fill && over_map => self.fill_region(mouse_pos),
+            None => vec![],
+        }
+    }
+
+    // Paints cells with the picked tile as a single undo step
+    fn paint_cells(&mut self, cells: Vec<usize>, wall: bool) {
+        if cells.is_empty() {
+            return;
+        }
+        self.history.end_step();
+        let tile = self.paint_tile(wall);
+        for idx in cells {
+            self.set_tile(idx, tile);
+        }
+        self.history.end_step();
+        self.palette.remember(&self.picked_tile);
+    }
+
+    // A button going down on the map with the current tool, or being held there
+    fn use_tool(&mut self, pos: (i32, i32), wall: bool, pressed: bool) {
+        match self.tool {
+            Tool::Brush if self.in_bounds(pos) => {
+                if wall { self.handle_r_click(pos) } else { self.handle_l_click(pos) }
+                self.palette.remember(&self.picked_tile);
+            }
+            Tool::Fill if pressed => {
+                let cells = self.fill_region(pos);
+                self.paint_cells(cells, wall);
+            }
+            shape if pressed && shape.is_shape() && self.in_bounds(pos) => {
+                self.drag = Some(Drag { start: pos, wall });
+            }
+            _ => {}
+        }
     }
 
     fn handle_e_click(&mut self, pos: (i32, i32)) {
@@ -74,41 +309,175 @@ impl MapEditorState {
             return;
         }
         let indx = self.xy_idx(pos.0, pos.1);
-        self.entities[indx] = self.picked_entity.clone();
+        self.set_entity(indx, self.picked_entity.clone());
     }
 
-    fn export_to_file(&self) {
-        let mut file = File::create("output.map").unwrap();
-        let s_str = serde_json::to_string(&MapDescriptor {
-            tiles: self.map_tiles.clone(),
-            width: self.width,
-            height: self.height,
-            entities: self.entities.clone(),
-        })
-        .unwrap();
-        file.write(s_str.as_bytes()).unwrap();
+    /*
+     * Writes the map to file_name, which becomes where S saves from then on.
+     * If the map was resized since it was saved there, portals leading into it from other maps are moved to match.
+     */
+    fn save_to(&mut self, file_name: &str) -> Result<String, String> {
+        if self.file_name.as_deref() != Some(file_name) {
+            // The file the shift applied to is left as it was
+            self.portal_shift.clear();
+        }
+        editor_files::write_map(file_name, &self.descriptor())?;
+        self.file_name = Some(file_name.to_string());
+        self.history.mark_saved();
+
+        // Whatever isn't written stays pending and is tried again on the next save
+        let mut moved = vec![];
+        for other in editor_files::maps_to_shift(file_name, &self.portal_shift)? {
+            editor_files::write_map(&other.file_name, &other.md)?;
+            self.portal_shift.record(&other.file_name, other.shift);
+            moved.push(other.file_name);
+        }
+        self.portal_shift.clear();
+
+        if moved.is_empty() {
+            Ok(format!("Saved {}", file_name))
+        } else {
+            Ok(format!("Saved {}, moved portals in {}", file_name, moved.join(", ")))
+        }
+    }
+
+    fn save(&mut self) {
+        match self.file_name.clone() {
+            Some(file_name) => self.notice = Some(self.save_to(&file_name).unwrap_or_else(|e| e)),
+            None => self.dialog = Some(FileDialog::save_as(None)),
+        }
+    }
+
+    // Does what a file dialog asked for, leaving it open with the error if that fails
+    fn on_dialog_key(&mut self, key: VirtualKeyCode, shift: bool) {
+        let dialog = match self.dialog.as_mut() {
+            Some(dialog) => dialog,
+            None => return,
+        };
+        let done = match dialog.on_key(key, shift) {
+            DialogEvent::Stay => return,
+            DialogEvent::Cancel => Ok(None),
+            DialogEvent::Open(file_name) => self.load_from_file(&file_name).map(|_| Some(format!("Opened {}", file_name))),
+            DialogEvent::SaveAs(file_name) => self.save_to(&file_name).map(Some),
+            DialogEvent::New(width, height) => {
+                self.new_map(width, height);
+                Ok(None)
+            }
+            DialogEvent::Resize(width, height, anchor) => {
+                self.resize(width, height, anchor);
+                Ok(None)
+            }
+        };
+        match done {
+            Ok(notice) => {
+                self.dialog = None;
+                self.notice = notice;
+            }
+            Err(e) => {
+                if let Some(dialog) = self.dialog.as_mut() {
+                    dialog.fail(e);
+                }
+            }
+        }
+    }
+
+    // The entity to place from its name in the raws, monsters are marked red and items blue
+    fn pick_entity(&self, name: &str) -> Result<MEEntity, String> {
+        let d = match (self.monsters.get(name), self.items.get(name)) {
+            (Some(raw), _) => Display { glyph: raw.display().glyph, fg: rltk::BLACK, bg: rltk::RED },
+            (None, Some(raw)) => Display { glyph: raw.display().glyph, fg: rltk::BLACK, bg: rltk::BLUE },
+            (None, None) => return Err(format!("No monster or item named {}", name)),
+        };
+        Ok(MEEntity { name: name.to_string(), d })
+    }
+
+    fn on_prompt_key(&mut self, key: VirtualKeyCode, shift: bool) {
+        let event = match self.prompt.as_mut() {
+            Some(prompt) => prompt.on_key(key, shift),
+            None => return,
+        };
+        match event {
+            PromptEvent::Stay => {}
+            PromptEvent::Cancel => self.prompt = None,
+            PromptEvent::Entity(name) => match self.pick_entity(&name) {
+                Ok(entity) => {
+                    self.picked_entity = Some(entity);
+                    self.prompt = None;
+                }
+                Err(e) => {
+                    if let Some(prompt) = self.prompt.as_mut() {
+                        prompt.fail(e);
+                    }
+                }
+            },
+            PromptEvent::Portal { at, destination, x, y } => {
+                self.prompt = None;
+                let idx = self.xy_idx(at.0, at.1);
+                self.set_tile(idx, TileType::Portal(self.picked_tile, destination, x, y));
+                self.history.end_step();
+            }
+        }
+    }
+
+    // File name and whether it has unsaved changes, along the bottom of the window
+    fn draw_status(&self, ctx: &mut Rltk) {
+        let y = crate::launcher::CONSOLE_HEIGHT as i32 - 1;
+        let name = self.file_name.as_deref().unwrap_or("untitled");
+        let (name, fg) = if self.history.is_dirty() {
+            (format!("{} *", name), rltk::YELLOW)
+        } else {
+            (name.to_string(), rltk::GREY)
+        };
+        let hints = self.notice.as_deref().unwrap_or("S save  Shift+S save as  ^O open  ^N new  ^R resize  ^Z undo  ^Y redo");
+        ctx.print_color(0, y, fg, rltk::BLACK, &name);
+        ctx.print_color(name.len() as i32 + 2, y, rltk::GREY, rltk::BLACK, hints);
+    }
+
+    fn draw_tools(&self, ctx: &mut Rltk, mouse_pos: (i32, i32), over_map: bool) {
+        let t = &self.picked_tile;
+        for idx in self.pending_cells(mouse_pos, over_map) {
+            let screen = self.to_screen((idx as i32 % self.width, idx as i32 / self.width));
+            if Self::in_view(screen) {
+                ctx.set(screen.0, screen.1, t.fg, t.bg, t.glyph);
+            }
+        }
+
+        // Fits left of the palette, Esc drops a shape being dragged
+        let tools = Tool::ALL.iter()
+            .enumerate()
+            .map(|(i, tool)| if *tool == self.tool { format!("[{} {}]", i + 1, tool.name()) } else { format!("{} {}", i + 1, tool.name()) })
+            .collect::<Vec<_>>()
+            .join(" ");
+        ctx.print_color(0, MAP_VIEW_HEIGHT, rltk::GREY, rltk::BLACK, tools);
+
+        let view = format!("{}x{} at {},{}  arrows scroll", self.width, self.height, self.camera.0, self.camera.1);
+        ctx.print_color(editor_palette::PANEL_X + 1, MAP_VIEW_HEIGHT, rltk::GREY, rltk::BLACK, view);
     }
 
     fn draw_map(&self, ctx: &mut Rltk) {
-        for i in 0..self.width {
-            for j in 0..self.height {
+        for sx in 0..MAP_VIEW_WIDTH {
+            for sy in 0..MAP_VIEW_HEIGHT {
+                let (i, j) = self.to_map((sx, sy));
+                if !self.in_bounds((i, j)) {
+                    continue;
+                }
                 let idx = self.xy_idx(i, j);
 
                 match self.map_tiles[idx] {
                     TileType::Floor(ref t) => {
-                        ctx.set(i, j, t.fg, t.bg, t.glyph);
+                        ctx.set(sx, sy, t.fg, t.bg, t.glyph);
                     }
                     TileType::Wall(ref t) => {
-                        ctx.set(i, j, t.fg, t.bg, t.glyph);
+                        ctx.set(sx, sy, t.fg, t.bg, t.glyph);
                     }
                     TileType::Portal(ref t, _, _, _) => {
-                        ctx.set(i, j, t.fg, t.bg, t.glyph);
+                        ctx.set(sx, sy, t.fg, t.bg, t.glyph);
                     }
                 }
 
                 match self.entities[idx] {
                     Some(ref e) => {
-                        ctx.set(i, j, e.d.fg, e.d.bg, e.d.glyph);
+                        ctx.set(sx, sy, e.d.fg, e.d.bg, e.d.glyph);
                     }
                     None => {}
                 }
@@ -116,117 +485,136 @@ impl MapEditorState {
         }
     }
 
-    fn get_input(&self) -> String {
-        let mut input = String::new();
-        stdin().read_line(&mut input).expect("Failed to read line");
-        input.trim().to_string()
-    }
 }
 
 impl GameState for MapEditorState {
     fn tick(&mut self, ctx: &mut Rltk) {
         ctx.cls();
 
-        self.draw_map(ctx);
+        // mouse_pos is where the mouse is on the map, screen where it is in the window
+        let screen = ctx.mouse_pos();
+        let mouse_pos = self.to_map(screen);
+        let over_map = Self::in_view(screen);
 
-        let mouse_pos = ctx.mouse_pos();
+        self.draw_map(ctx);
+        self.draw_tools(ctx, mouse_pos, over_map);
+        self.draw_status(ctx);
+        self.palette.render(ctx, &self.picked_tile, screen);
 
         let a = &rltk::INPUT;
         let right_click = a.lock().is_mouse_button_pressed(1);
-        if ctx.left_click {
-            if ctx.shift {
-                self.handle_e_click(mouse_pos);
-            } else {
-                self.handle_l_click(mouse_pos);
+        let left_held = a.lock().is_mouse_button_pressed(0);
+        // left_click is also set on the frame the button comes back up
+        let left_pressed = ctx.left_click && left_held;
+        let right_pressed = right_click && !self.right_was_held;
+        self.right_was_held = right_click;
+
+        // A file dialog takes every key until it closes, and the mouse does nothing meanwhile
+        if let Some(dialog) = &self.dialog {
+            dialog.render(ctx);
+            self.history.end_step();
+            if let Some(key) = ctx.key {
+                self.on_dialog_key(key, ctx.shift);
             }
+            return;
+        }
+        if let Some(prompt) = &self.prompt {
+            prompt.render(ctx);
+            if let Some(key) = ctx.key {
+                self.on_prompt_key(key, ctx.shift);
+            }
+            return;
+        }
+
+        if let Some(drag) = self.drag {
+            // The shape lands once the button that started it comes back up
+            let held = if drag.wall { right_click } else { left_held };
+            if !held {
+                let cells = self.pending_cells(mouse_pos, over_map);
+                self.drag = None;
+                self.paint_cells(cells, drag.wall);
+            }
+        } else if self.palette.contains(screen) {
+            self.palette.on_mouse(screen, &mut self.picked_tile, left_pressed, left_held, right_pressed);
+        } else if !over_map {
+            // The tool bar and status line
+        } else if ctx.left_click && ctx.shift {
+            self.handle_e_click(mouse_pos);
+        } else if left_held {
+            self.use_tool(mouse_pos, false, left_pressed);
         } else if right_click {
-            self.handle_r_click(mouse_pos);
+            self.use_tool(mouse_pos, true, right_pressed);
+        }
+
+        // A stroke is one undo step, it ends once every button is let go
+        if !right_click && !left_held && !ctx.left_click {
+            self.history.end_step();
+        }
+
+        if ctx.key.is_some() {
+            self.notice = None;
         }
 
         match ctx.key {
-            Some(VirtualKeyCode::K) => {
-                let _ = stdout().flush();
-                let mut new_glyph = String::new();
-                stdin().read_line(&mut new_glyph).expect("Invalid input");
-                let n_glyph_char = new_glyph.trim().parse::<char>().unwrap() as u16;
-                self.picked_tile.glyph = n_glyph_char;
-
-                new_glyph = String::new();
-                stdin().read_line(&mut new_glyph).expect("Invalid input");
-
-                // Self::remove_trailing_new_line(&mut new_glyph);
-                let fg = new_glyph
-                    .split(",")
-                    .map(|a| a.trim())
-                    .map(|a| a.parse::<u8>().unwrap())
-                    .collect::<Vec<u8>>();
-                let fg = (fg[0], fg[1], fg[2]);
-
-                new_glyph = String::new();
-                stdin().read_line(&mut new_glyph).expect("Invalid input");
-
-                let bg = new_glyph
-                    .split(",")
-                    .map(|a| a.trim())
-                    .map(|a| a.parse::<u8>().unwrap())
-                    .collect::<Vec<u8>>();
-                let bg = (bg[0], bg[1], bg[2]);
-
-                self.picked_tile.bg = bg;
-                self.picked_tile.fg = fg;
+            Some(VirtualKeyCode::Escape) => {
+                self.drag = None;
+            }
+            Some(key @ (VirtualKeyCode::Left | VirtualKeyCode::Right | VirtualKeyCode::Up | VirtualKeyCode::Down)) => {
+                let step = if ctx.shift { 10 } else { 1 };
+                match key {
+                    VirtualKeyCode::Left => self.scroll(-step, 0),
+                    VirtualKeyCode::Right => self.scroll(step, 0),
+                    VirtualKeyCode::Up => self.scroll(0, -step),
+                    _ => self.scroll(0, step),
+                }
+            }
+            Some(VirtualKeyCode::O) if ctx.control => {
+                self.dialog = Some(FileDialog::open(self.history.is_dirty()));
+            }
+            Some(VirtualKeyCode::N) if ctx.control => {
+                self.dialog = Some(FileDialog::new_map(self.history.is_dirty()));
+            }
+            Some(VirtualKeyCode::R) if ctx.control => {
+                self.dialog = Some(FileDialog::resize(self.width, self.height));
             }
-            Some(VirtualKeyCode::R) => {
+            Some(VirtualKeyCode::S) if ctx.shift => {
+                self.dialog = Some(FileDialog::save_as(self.file_name.as_deref()));
+            }
+            Some(key @ (VirtualKeyCode::Key1 | VirtualKeyCode::Key2 | VirtualKeyCode::Key3 | VirtualKeyCode::Key4 | VirtualKeyCode::Key5)) => {
+                self.tool = Tool::ALL[key as usize - VirtualKeyCode::Key1 as usize];
+                self.drag = None;
+            }
+            Some(VirtualKeyCode::Z) if ctx.control && ctx.shift => {
+                self.history.redo(&mut self.map_tiles, &mut self.entities);
+            }
+            Some(VirtualKeyCode::Z) if ctx.control => {
+                self.history.undo(&mut self.map_tiles, &mut self.entities);
+            }
+            Some(VirtualKeyCode::Y) if ctx.control => {
+                self.history.redo(&mut self.map_tiles, &mut self.entities);
+            }
+            Some(VirtualKeyCode::R) if over_map && self.in_bounds(mouse_pos) => {
                 let idx = self.xy_idx(mouse_pos.0, mouse_pos.1);
-                self.entities[idx] = None;
+                self.set_entity(idx, None);
+                self.history.end_step();
             }
             Some(VirtualKeyCode::E) => {
-                let _ = stdout().flush();
-                let entity_name = self.get_input();
-                let entity_display = match entity_name.as_str() {
-                    "Goblin" => Display {
-                        glyph: 'g' as u16,
-                        fg: rltk::BLACK,
-                        bg: rltk::RED,
-                    },
-                    "SFElemental" => Display {
-                        glyph: '*' as u16,
-                        fg: rltk::BLACK,
-                        bg: rltk::RED,
-                    },
-                    "Spider" => Display {
-                        glyph: 's' as u16,
-                        fg: rltk::BLACK,
-                        bg: rltk::RED,
-                    },
-                    "KSpider" => Display {
-                        glyph: 'S' as u16,
-                        fg: rltk::BLACK,
-                        bg: rltk::RED,
-                    },
-                    _ => Display {
-                        glyph: '?' as u16,
-                        fg: rltk::BLACK,
-                        bg: rltk::RED,
-                    },
-                };
-                self.picked_entity = Some(MEEntity {
-                    name: entity_name,
-                    d: entity_display,
-                });
+                self.prompt = Some(Prompt::entity(self.picked_entity.as_ref().map(|e| e.name.as_str())));
             }
             Some(VirtualKeyCode::S) => {
-                self.export_to_file();
+                self.save();
             }
             Some(VirtualKeyCode::L) => {
-                let md = structs::map_utils::load_from_file("output.map");
-                self.map_tiles = md.tiles;
-                self.width = md.width;
-                self.height = md.height;
-                self.entities = md.entities;
+                // Throws away everything since the last save
+                if let Some(file_name) = self.file_name.clone() {
+                    if let Err(e) = self.load_from_file(&file_name) {
+                        self.notice = Some(e);
+                    }
+                }
             }
             Some(VirtualKeyCode::P) => {
                 let pos = mouse_pos;
-                if self.in_bounds(pos) {
+                if over_map && self.in_bounds(pos) {
                     let idx = self.xy_idx(pos.0, pos.1);
                     self.picked_tile = match self.map_tiles[idx] {
                         TileType::Floor(ref t) => t.clone(),
@@ -237,20 +625,17 @@ impl GameState for MapEditorState {
             }
             Some(VirtualKeyCode::F) => {
                 //Fill map with current tile as floor
+                self.history.end_step();
                 for i in 0..self.width {
                     for j in 0..self.height {
                         let idx = self.xy_idx(i, j);
-                        self.map_tiles[idx] = TileType::Floor(self.picked_tile.clone());
+                        self.set_tile(idx, TileType::Floor(self.picked_tile));
                     }
                 }
+                self.history.end_step();
             }
-            Some(VirtualKeyCode::A) => {
-                let _ = stdout().flush();
-                let portal_dir = self.get_input().parse::<usize>().unwrap();
-                let x = self.get_input().parse::<i32>().unwrap();
-                let y = self.get_input().parse::<i32>().unwrap();
-                let idx = self.xy_idx(mouse_pos.0, mouse_pos.1);
-                self.map_tiles[idx] = TileType::Portal(self.picked_tile.clone(), portal_dir, x, y);
+            Some(VirtualKeyCode::A) if over_map && self.in_bounds(mouse_pos) => {
+                self.prompt = Some(Prompt::portal(mouse_pos));
             }
             Some(_) => {}
             None => {}
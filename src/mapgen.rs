@@ -0,0 +1,491 @@
+use std::collections::VecDeque;
+use std::fs;
+
+use rltk::RandomNumberGenerator;
+use serde::{Deserialize, Serialize};
+
+use crate::map_editor::MEEntity;
+use crate::raws::MonsterRegistry;
+use crate::structs::map_utils::MapDescriptor;
+use crate::structs::{Display, TileType};
+
+const MIN_MAP_SIZE: i32 = 12;
+// A floor the start reaches less of than this is thrown away and generated again
+const MIN_FLOOR_TILES: usize = 20;
+const MAX_ATTEMPTS: usize = 10;
+
+#[derive(Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum MapAlgorithm {
+    RoomsAndCorridors,
+    Bsp,
+    CellularAutomata,
+    DrunkardsWalk,
+}
+
+/*
+ * Everything needed to build one generated floor.
+ * spawns lists monster names from the raws, spawn_count of them are placed at random.
+ * exit, if set, puts a portal (destination, x, y) on the floor tile furthest from the start.
+ */
+#[derive(Clone, Serialize, Deserialize)]
+pub struct GeneratorSettings {
+    pub algorithm: MapAlgorithm,
+    pub width: i32,
+    pub height: i32,
+    pub spawns: Vec<String>,
+    pub spawn_count: usize,
+    #[serde(default)]
+    pub exit: Option<(usize, i32, i32)>,
+}
+
+// Where a portal destination gets its map from
+#[derive(Clone, Serialize, Deserialize)]
+pub enum MapSource {
+    File(String),
+    Generated(GeneratorSettings),
+    // A map given as is, used when driving the game without files
+    Inline(MapDescriptor),
+}
+
+impl MapSource {
+    pub fn build(&self, registry: &MonsterRegistry, rng: &mut RandomNumberGenerator) -> MapDescriptor {
+        match self {
+            Self::File(file_name) => crate::structs::map_utils::load_from_file(file_name),
+            Self::Generated(settings) => generate(settings, registry, rng),
+            Self::Inline(map) => map.clone(),
+        }
+    }
+}
+
+/*
+ * Reads the list of portal destinations, index i in the list is portal destination i.
+ * Falls back to the two hand-made maps if the file is missing or malformed.
+ */
+pub fn load_destinations(file_name: &str) -> Vec<MapSource> {
+    let parsed = fs::read_to_string(file_name)
+        .map_err(|e| e.to_string())
+        .and_then(|contents| serde_json::from_str::<Vec<MapSource>>(&contents).map_err(|e| e.to_string()));
+
+    match parsed {
+        Ok(destinations) if !destinations.is_empty() => destinations,
+        Ok(_) => {
+            eprintln!("{} has no destinations, using the default maps", file_name);
+            default_destinations()
+        }
+        Err(e) => {
+            eprintln!("Could not load {}: {}, using the default maps", file_name, e);
+            default_destinations()
+        }
+    }
+}
+
+fn default_destinations() -> Vec<MapSource> {
+    vec![
+        MapSource::File("main.map".to_string()),
+        MapSource::File("portal_land.map".to_string()),
+    ]
+}
+
+#[derive(Copy, Clone)]
+struct Rect {
+    x1: i32,
+    y1: i32,
+    x2: i32,
+    y2: i32,
+}
+
+impl Rect {
+    fn new(x: i32, y: i32, w: i32, h: i32) -> Self {
+        Rect { x1: x, y1: y, x2: x + w, y2: y + h }
+    }
+
+    fn intersects(&self, other: &Rect) -> bool {
+        self.x1 <= other.x2 && self.x2 >= other.x1 && self.y1 <= other.y2 && self.y2 >= other.y1
+    }
+
+    fn center(&self) -> (i32, i32) {
+        ((self.x1 + self.x2) / 2, (self.y1 + self.y2) / 2)
+    }
+}
+
+// Working grid of the generators, true is floor
+struct Grid {
+    width: i32,
+    height: i32,
+    floor: Vec<bool>,
+}
+
+impl Grid {
+    fn new(width: i32, height: i32) -> Self {
+        Grid { width, height, floor: vec![false; (width * height) as usize] }
+    }
+
+    fn idx(&self, x: i32, y: i32) -> usize {
+        (y * self.width + x) as usize
+    }
+
+    // The outer ring always stays wall
+    fn in_inner_bounds(&self, x: i32, y: i32) -> bool {
+        x > 0 && x < self.width - 1 && y > 0 && y < self.height - 1
+    }
+
+    fn dig(&mut self, x: i32, y: i32) {
+        if self.in_inner_bounds(x, y) {
+            let idx = self.idx(x, y);
+            self.floor[idx] = true;
+        }
+    }
+
+    fn dig_room(&mut self, room: &Rect) {
+        for y in room.y1 + 1..=room.y2 {
+            for x in room.x1 + 1..=room.x2 {
+                self.dig(x, y);
+            }
+        }
+    }
+
+    fn dig_corridor(&mut self, from: (i32, i32), to: (i32, i32), rng: &mut RandomNumberGenerator) {
+        let (x1, y1) = from;
+        let (x2, y2) = to;
+        if rng.range(0, 2) == 0 {
+            (x1.min(x2)..=x1.max(x2)).for_each(|x| self.dig(x, y1));
+            (y1.min(y2)..=y1.max(y2)).for_each(|y| self.dig(x2, y));
+        } else {
+            (y1.min(y2)..=y1.max(y2)).for_each(|y| self.dig(x1, y));
+            (x1.min(x2)..=x1.max(x2)).for_each(|x| self.dig(x, y2));
+        }
+    }
+
+    fn floor_neighbours(&self, x: i32, y: i32) -> usize {
+        let mut count = 0;
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                if (dx != 0 || dy != 0) && self.in_inner_bounds(x + dx, y + dy) && self.floor[self.idx(x + dx, y + dy)] {
+                    count += 1;
+                }
+            }
+        }
+        count
+    }
+
+    fn nearest_floor(&self, pos: (i32, i32)) -> Option<(i32, i32)> {
+        (0..self.floor.len())
+            .filter(|i| self.floor[*i])
+            .map(|i| (i as i32 % self.width, i as i32 / self.width))
+            .min_by_key(|(x, y)| (x - pos.0).abs() + (y - pos.1).abs())
+    }
+
+    // Breadth first distances from start over floor tiles, None for unreachable
+    fn distances_from(&self, start: (i32, i32)) -> Vec<Option<i32>> {
+        let mut dist = vec![None; self.floor.len()];
+        let mut open = VecDeque::new();
+        dist[self.idx(start.0, start.1)] = Some(0);
+        open.push_back(start);
+
+        while let Some((x, y)) = open.pop_front() {
+            let d = dist[self.idx(x, y)].unwrap();
+            for (dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1)] {
+                let (nx, ny) = (x + dx, y + dy);
+                if !self.in_inner_bounds(nx, ny) { continue; }
+                let n_idx = self.idx(nx, ny);
+                if self.floor[n_idx] && dist[n_idx].is_none() {
+                    dist[n_idx] = Some(d + 1);
+                    open.push_back((nx, ny));
+                }
+            }
+        }
+        dist
+    }
+}
+
+fn rooms_and_corridors(grid: &mut Grid, rng: &mut RandomNumberGenerator) -> (i32, i32) {
+    const MAX_ROOMS: i32 = 30;
+    const MIN_SIZE: i32 = 4;
+    const MAX_SIZE: i32 = 10;
+
+    let mut rooms: Vec<Rect> = vec![];
+    for _ in 0..MAX_ROOMS {
+        let w = rng.range(MIN_SIZE, MAX_SIZE.min(grid.width - 3));
+        let h = rng.range(MIN_SIZE, MAX_SIZE.min(grid.height - 3));
+        let x = rng.range(0, grid.width - w - 1);
+        let y = rng.range(0, grid.height - h - 1);
+        let room = Rect::new(x, y, w, h);
+
+        if rooms.iter().any(|r| r.intersects(&room)) { continue; }
+
+        grid.dig_room(&room);
+        if let Some(prev) = rooms.last() {
+            grid.dig_corridor(prev.center(), room.center(), rng);
+        }
+        rooms.push(room);
+    }
+
+    rooms.first()
+        .map(|r| r.center())
+        .unwrap_or((grid.width / 2, grid.height / 2))
+}
+
+fn bsp(grid: &mut Grid, rng: &mut RandomNumberGenerator) -> (i32, i32) {
+    const MIN_LEAF: i32 = 8;
+
+    // Returns the centers of the rooms dug inside area so siblings can be joined
+    fn split(grid: &mut Grid, area: Rect, rng: &mut RandomNumberGenerator) -> Vec<(i32, i32)> {
+        let w = area.x2 - area.x1;
+        let h = area.y2 - area.y1;
+        let can_split_v = w >= MIN_LEAF * 2;
+        let can_split_h = h >= MIN_LEAF * 2;
+
+        if !can_split_v && !can_split_h {
+            let rw = rng.range(3, (w - 1).max(4));
+            let rh = rng.range(3, (h - 1).max(4));
+            let rx = area.x1 + rng.range(0, (w - rw).max(1));
+            let ry = area.y1 + rng.range(0, (h - rh).max(1));
+            let room = Rect::new(rx, ry, rw, rh);
+            grid.dig_room(&room);
+            return vec![room.center()];
+        }
+
+        let vertical = if can_split_v && can_split_h { w > h } else { can_split_v };
+        let (a, b) = if vertical {
+            let at = rng.range(area.x1 + MIN_LEAF, area.x2 - MIN_LEAF + 1);
+            (Rect { x2: at, ..area }, Rect { x1: at, ..area })
+        } else {
+            let at = rng.range(area.y1 + MIN_LEAF, area.y2 - MIN_LEAF + 1);
+            (Rect { y2: at, ..area }, Rect { y1: at, ..area })
+        };
+
+        let mut left = split(grid, a, rng);
+        let right = split(grid, b, rng);
+        grid.dig_corridor(left[0], right[0], rng);
+        left.extend(right);
+        left
+    }
+
+    let area = Rect::new(0, 0, grid.width - 1, grid.height - 1);
+    split(grid, area, rng)[0]
+}
+
+fn cellular_automata(grid: &mut Grid, rng: &mut RandomNumberGenerator) -> (i32, i32) {
+    for y in 0..grid.height {
+        for x in 0..grid.width {
+            if grid.in_inner_bounds(x, y) && rng.range(0, 100) >= 45 {
+                let idx = grid.idx(x, y);
+                grid.floor[idx] = true;
+            }
+        }
+    }
+
+    for _ in 0..5 {
+        let mut next = grid.floor.clone();
+        for y in 1..grid.height - 1 {
+            for x in 1..grid.width - 1 {
+                let walls = 8 - grid.floor_neighbours(x, y);
+                next[grid.idx(x, y)] = walls < 5;
+            }
+        }
+        grid.floor = next;
+    }
+
+    (grid.width / 2, grid.height / 2)
+}
+
+fn drunkards_walk(grid: &mut Grid, rng: &mut RandomNumberGenerator) -> (i32, i32) {
+    const FLOOR_PERCENT: usize = 40;
+    const WALKER_LIFETIME: i32 = 400;
+
+    let start = (grid.width / 2, grid.height / 2);
+    let wanted = grid.floor.len() * FLOOR_PERCENT / 100;
+    grid.dig(start.0, start.1);
+
+    // Every walker starts from the center so the result is always connected
+    while grid.floor.iter().filter(|f| **f).count() < wanted {
+        let (mut x, mut y) = start;
+        for _ in 0..WALKER_LIFETIME {
+            match rng.range(0, 4) {
+                0 => x -= 1,
+                1 => x += 1,
+                2 => y -= 1,
+                _ => y += 1,
+            }
+            x = x.clamp(1, grid.width - 2);
+            y = y.clamp(1, grid.height - 2);
+            grid.dig(x, y);
+        }
+    }
+
+    start
+}
+
+/*
+ * Runs the algorithm once and fills back in anything the start can't reach, so the floor is one connected region.
+ * Returns the grid, the start and how far every tile is from it.
+ */
+fn carve(settings: &GeneratorSettings, rng: &mut RandomNumberGenerator) -> (Grid, (i32, i32), Vec<Option<i32>>) {
+    // Smaller maps leave no room for the generators to work with
+    let mut grid = Grid::new(settings.width.max(MIN_MAP_SIZE), settings.height.max(MIN_MAP_SIZE));
+
+    let start = match settings.algorithm {
+        MapAlgorithm::RoomsAndCorridors => rooms_and_corridors(&mut grid, rng),
+        MapAlgorithm::Bsp => bsp(&mut grid, rng),
+        MapAlgorithm::CellularAutomata => cellular_automata(&mut grid, rng),
+        MapAlgorithm::DrunkardsWalk => drunkards_walk(&mut grid, rng),
+    };
+
+    // The start may have landed on a wall for cave style maps
+    let start = grid.nearest_floor(start).unwrap_or_else(|| {
+        grid.dig(start.0, start.1);
+        start
+    });
+
+    let distances = grid.distances_from(start);
+    for (idx, d) in distances.iter().enumerate() {
+        if d.is_none() {
+            grid.floor[idx] = false;
+        }
+    }
+    (grid, start, distances)
+}
+
+// One open room, for when the algorithm keeps leaving too little floor
+fn open_room(settings: &GeneratorSettings) -> (Grid, (i32, i32), Vec<Option<i32>>) {
+    let mut grid = Grid::new(settings.width.max(MIN_MAP_SIZE), settings.height.max(MIN_MAP_SIZE));
+    grid.dig_room(&Rect::new(0, 0, grid.width - 2, grid.height - 2));
+    let start = (grid.width / 2, grid.height / 2);
+    let distances = grid.distances_from(start);
+    (grid, start, distances)
+}
+
+pub fn generate(settings: &GeneratorSettings, registry: &MonsterRegistry, rng: &mut RandomNumberGenerator) -> MapDescriptor {
+    let (grid, start, distances) = (0..MAX_ATTEMPTS)
+        .map(|_| carve(settings, rng))
+        .find(|(_, _, distances)| distances.iter().flatten().count() >= MIN_FLOOR_TILES)
+        .unwrap_or_else(|| open_room(settings));
+
+    let mut reachable = (0..grid.floor.len())
+        .filter(|i| distances[*i].is_some_and(|d| d > 3))
+        .collect::<Vec<_>>();
+
+    let floor = Display { glyph: rltk::to_cp437('.'), fg: rltk::WHITE, bg: rltk::BLACK };
+    let wall = Display { glyph: rltk::to_cp437('#'), fg: (120, 60, 60), bg: (60, 30, 30) };
+
+    let mut tiles = grid.floor
+        .iter()
+        .map(|f| if *f { TileType::Floor(floor) } else { TileType::Wall(wall) })
+        .collect::<Vec<_>>();
+
+    if let Some((destination, x, y)) = settings.exit {
+        let furthest = (0..distances.len()).max_by_key(|i| distances[*i].unwrap_or(-1));
+        if let Some(idx) = furthest {
+            let portal = Display { glyph: rltk::to_cp437('>'), fg: rltk::YELLOW, bg: rltk::BLACK };
+            tiles[idx] = TileType::Portal(portal, destination, x, y);
+            reachable.retain(|i| *i != idx);
+        }
+    }
+
+    let mut entities = vec![None; tiles.len()];
+    if !settings.spawns.is_empty() {
+        for _ in 0..settings.spawn_count {
+            let slot = match rng.random_slice_index(&reachable) {
+                Some(slot) => slot,
+                None => break,
+            };
+            let idx = reachable.swap_remove(slot);
+            let name = rng.random_slice_entry(&settings.spawns).unwrap().clone();
+            let d = registry.get(name.as_str())
+                .map(|r| r.display())
+                .unwrap_or(Display { glyph: rltk::to_cp437('?'), fg: rltk::BLACK, bg: rltk::RED });
+            entities[idx] = Some(MEEntity { d, name });
+        }
+    }
+
+    MapDescriptor {
+        width: grid.width,
+        height: grid.height,
+        tiles,
+        entities,
+        player_start: Some(start),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALGORITHMS: [MapAlgorithm; 4] = [
+        MapAlgorithm::RoomsAndCorridors,
+        MapAlgorithm::Bsp,
+        MapAlgorithm::CellularAutomata,
+        MapAlgorithm::DrunkardsWalk,
+    ];
+
+    fn settings(algorithm: MapAlgorithm, width: i32, height: i32) -> GeneratorSettings {
+        GeneratorSettings {
+            algorithm,
+            width,
+            height,
+            spawns: vec!["Goblin".to_string()],
+            spawn_count: 8,
+            exit: Some((0, 1, 1)),
+        }
+    }
+
+    // Every tile that isn't a wall, walking from the start
+    fn walkable_from_start(md: &MapDescriptor) -> Vec<bool> {
+        let mut grid = Grid::new(md.width, md.height);
+        grid.floor = md.tiles.iter().map(|t| !matches!(t, TileType::Wall(_))).collect();
+        let start = md.player_start.unwrap();
+        grid.distances_from(start).iter().map(|d| d.is_some()).collect()
+    }
+
+    #[test]
+    fn every_algorithm_makes_one_connected_floor_with_a_reachable_exit() {
+        let registry = MonsterRegistry::load_from_file("monsters.json").unwrap();
+        for algorithm in ALGORITHMS {
+            for (width, height) in [(MIN_MAP_SIZE, MIN_MAP_SIZE), (48, 48), (60, 40)] {
+                for seed in 0..10 {
+                    let md = generate(&settings(algorithm, width, height), &registry, &mut RandomNumberGenerator::seeded(seed));
+                    let start = md.player_start.unwrap();
+                    let start_idx = (start.1 * md.width + start.0) as usize;
+                    let reached = walkable_from_start(&md);
+
+                    assert!(matches!(md.tiles[start_idx], TileType::Floor(_)));
+                    for (idx, tile) in md.tiles.iter().enumerate() {
+                        assert!(matches!(tile, TileType::Wall(_)) || reached[idx], "cut off floor at {} with seed {}", idx, seed);
+                    }
+                    let exits = md.tiles.iter().enumerate().filter(|(_, t)| matches!(t, TileType::Portal(..))).map(|(i, _)| i).collect::<Vec<_>>();
+                    assert_eq!(exits.len(), 1);
+                    assert_ne!(exits[0], start_idx);
+                    assert!(md.entities.iter().enumerate().all(|(i, e)| e.is_none() || (reached[i] && i != start_idx && i != exits[0])));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn the_same_seed_makes_the_same_floor() {
+        let registry = MonsterRegistry::load_from_file("monsters.json").unwrap();
+        for algorithm in ALGORITHMS {
+            let make = || generate(&settings(algorithm, 48, 48), &registry, &mut RandomNumberGenerator::seeded(5));
+            let (a, b) = (make(), make());
+            assert!(a.tiles == b.tiles && a.entities == b.entities && a.player_start == b.player_start);
+        }
+    }
+
+    #[test]
+    fn too_little_floor_falls_back_to_an_open_room() {
+        let (grid, start, distances) = open_room(&settings(MapAlgorithm::CellularAutomata, 4, 4));
+        assert_eq!((grid.width, grid.height), (MIN_MAP_SIZE, MIN_MAP_SIZE));
+        assert!(grid.floor[grid.idx(start.0, start.1)]);
+        assert_eq!(distances.iter().flatten().count(), ((MIN_MAP_SIZE - 2) * (MIN_MAP_SIZE - 2)) as usize);
+    }
+
+    #[test]
+    fn main_maps_top_portal_leads_to_a_generated_floor() {
+        let main = crate::structs::map_utils::load_from_file("main.map");
+        let destinations = load_destinations("destinations.json");
+        match main.tiles[15] {
+            TileType::Portal(_, destination, _, _) => assert!(matches!(destinations[destination], MapSource::Generated(_))),
+            _ => panic!("main.map has no portal at (15, 0)"),
+        }
+    }
+}
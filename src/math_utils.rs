@@ -1,4 +1,5 @@
-use rand::Rng;
+use rltk::RandomNumberGenerator;
+use serde::{Deserialize, Serialize};
 
 pub fn clamp(value: i32, min: i32, max: i32) -> i32 {
     if value < min {
@@ -10,14 +11,47 @@ pub fn clamp(value: i32, min: i32, max: i32) -> i32 {
     }
 }
 
-pub fn chance(f: f32) -> bool {
-    return rand::thread_rng().gen::<f32>() < f;
+/*
+ * The one source of randomness for the game, so a run can be reproduced from its seed.
+ * The seed is what the game was started with, the generator itself moves on from there.
+ * Saving writes out the generator as it is, so a loaded game rolls exactly what the running one would.
+ */
+#[derive(Clone, Serialize, Deserialize)]
+pub struct GameRng {
+    seed: u64,
+    rng: RandomNumberGenerator,
 }
 
-#[allow(dead_code)]
-pub fn random_point(x1: i32, x2: i32, y1: i32, y2: i32) -> (i32, i32) {
-    let mut rng = rand::thread_rng();
-    let x = rng.gen_range(x1..x2);
-    let y = rng.gen_range(y1..y2);
-    return (x, y);
+impl GameRng {
+
+    pub fn seeded(seed: u64) -> Self {
+        GameRng {
+            seed,
+            rng: RandomNumberGenerator::seeded(seed),
+        }
+    }
+
+    pub fn from_entropy() -> Self {
+        Self::seeded(RandomNumberGenerator::new().next_u64())
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn rng(&mut self) -> &mut RandomNumberGenerator {
+        &mut self.rng
+    }
+
+    pub fn chance(&mut self, f: f32) -> bool {
+        self.rng.rand::<f32>() < f
+    }
+
+    #[allow(dead_code)]
+    pub fn random_point(&mut self, x1: i32, x2: i32, y1: i32, y2: i32) -> (i32, i32) {
+        let x = self.rng.range(x1, x2);
+        let y = self.rng.range(y1, y2);
+        (x, y)
+    }
+
 }
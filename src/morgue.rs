@@ -0,0 +1,208 @@
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rltk::{ColorPair, DrawBatch, Point, Rltk, VirtualKeyCode, RGB};
+
+use crate::scheduler;
+use crate::structs::{Container, Equipment};
+use crate::State;
+
+pub const MORGUE_DIR: &str = "morgue";
+
+// Messages from the end of the log kept in the summary
+const LAST_MESSAGES: usize = 10;
+
+/*
+ * How a run ended, put together the moment the player dies.
+ */
+#[derive(Clone)]
+pub struct DeathSummary {
+    pub killer: String,
+    pub seed: u64,
+    pub turns: u64,
+    pub depth: usize,
+    pub level: i32,
+    pub xp: i32,
+    pub stats: Vec<String>,
+    pub inventory: Vec<String>,
+    pub last_messages: Vec<String>,
+}
+
+impl DeathSummary {
+
+    pub fn from_state(state: &State) -> Self {
+        let player = state.ecs.get_player_id();
+        let stats = state.get_player_stat_block().clone();
+
+        let inventory = match (state.ecs.get::<Container>(player), state.ecs.get::<Equipment>(player)) {
+            (Ok(container), equipment) => container.items.iter()
+                .enumerate()
+                .map(|(i, item)| {
+                    let worn = equipment.as_ref().map(|e| e.equips.contains(&Some(i))).unwrap_or(false);
+                    if worn { format!("{} (equipped)", item.label()) } else { item.label() }
+                })
+                .collect(),
+            (Err(_), _) => vec![],
+        };
+
+        let entries = state.log.entries();
+        let last_messages = entries[entries.len().saturating_sub(LAST_MESSAGES)..]
+            .iter()
+            .map(|e| e.text.clone())
+            .collect();
+
+        DeathSummary {
+            killer: state.player_last_hit_by.clone().unwrap_or_else(|| "something unseen".to_string()),
+            seed: state.rng.seed(),
+            turns: state.game_ticks / scheduler::TICKS_PER_TURN,
+            depth: state.map_state.current_map + 1,
+            level: stats.level,
+            xp: stats.xp,
+            stats: vec![
+                format!("HP {} / {}", stats.hp.get_total(), stats.hp.get_max()),
+                format!("ATK {}", stats.atk.get_total()),
+                format!("DEF {}", stats.def.get_total()),
+            ],
+            inventory,
+            last_messages,
+        }
+    }
+
+    pub fn headline(&self) -> String {
+        format!("Killed by {} on depth {} after {} turns", self.killer, self.depth, self.turns)
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = vec![
+            "rust-rltk morgue file".to_string(),
+            String::new(),
+            self.headline(),
+            format!("Reached level {} with {} xp", self.level, self.xp),
+            format!("Seed {}", self.seed),
+            self.stats.join(", "),
+            String::new(),
+            "Inventory:".to_string(),
+        ];
+        if self.inventory.is_empty() {
+            text.push("  nothing".to_string());
+        }
+        text.extend(self.inventory.iter().map(|i| format!("  {}", i)));
+        text.push(String::new());
+        text.push("Last messages:".to_string());
+        text.extend(self.last_messages.iter().map(|m| format!("  {}", m)));
+        text.push(String::new());
+        text.join("\n")
+    }
+
+    // Writes the summary to a new file in the morgue directory, returns its path
+    pub fn write(&self) -> Result<String, String> {
+        self.write_in(MORGUE_DIR)
+    }
+
+    // Deaths within the same second get numbered instead of overwriting each other
+    fn write_in(&self, dir: &str) -> Result<String, String> {
+        let secs = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+
+        let mut n = 0;
+        loop {
+            let path = match n {
+                0 => format!("{}/morgue_{}.txt", dir, secs),
+                _ => format!("{}/morgue_{}_{}.txt", dir, secs, n),
+            };
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    file.write_all(self.to_text().as_bytes()).map_err(|e| e.to_string())?;
+                    return Ok(path);
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => n += 1,
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+    }
+
+}
+
+/*
+ * Shown in place of the game once the player dies, any of Enter, Space or Escape goes back to the menu.
+ */
+pub struct GameOverScreen {
+    summary: DeathSummary,
+    // Where the morgue file went, or why it couldn't be written
+    morgue: String,
+}
+
+impl GameOverScreen {
+
+    pub fn new(summary: DeathSummary, morgue: String) -> Self {
+        GameOverScreen { summary, morgue }
+    }
+
+    // True once the player is done looking
+    pub fn on_input(&self, ctx: &Rltk) -> bool {
+        matches!(
+            ctx.key,
+            Some(VirtualKeyCode::Return) | Some(VirtualKeyCode::NumpadEnter) | Some(VirtualKeyCode::Space) | Some(VirtualKeyCode::Escape)
+        )
+    }
+
+    pub fn render(&self, ctx: &mut Rltk) {
+        let mut g_db = DrawBatch::new();
+        g_db.cls();
+        let white = ColorPair::new(RGB::named(rltk::WHITE), RGB::named(rltk::BLACK));
+        let grey = ColorPair::new(RGB::named(rltk::GREY), RGB::named(rltk::BLACK));
+
+        g_db.print_color_centered(3, "Y O U   D I E D", ColorPair::new(RGB::named(rltk::RED), RGB::named(rltk::BLACK)));
+        g_db.print_color_centered(5, self.summary.headline(), white);
+        g_db.print_color_centered(6, format!("Level {} with {} xp", self.summary.level, self.summary.xp), white);
+        g_db.print_color_centered(7, self.summary.stats.join("   "), white);
+
+        g_db.print_color(Point::new(4, 10), "Inventory", white);
+        for (i, item) in self.summary.inventory.iter().take(12).enumerate() {
+            g_db.print_color(Point::new(5, 11 + i as i32), item, grey);
+        }
+
+        g_db.print_color(Point::new(4, 24), "Last messages", white);
+        for (i, message) in self.summary.last_messages.iter().enumerate() {
+            g_db.print_color(Point::new(5, 25 + i as i32), message, grey);
+        }
+
+        g_db.print_color_centered(36, &self.morgue, grey);
+        g_db.print_color_centered(38, "Press Enter to return to the menu", white);
+
+        g_db.submit(0).expect("Rendering error with draw batch");
+
+        rltk::render_draw_buffer(ctx).expect("Rendering error");
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deaths_in_the_same_second_get_their_own_files() {
+        let dir = std::env::temp_dir().join(format!("morgue_test_{}", std::process::id()));
+        let dir = dir.to_str().unwrap();
+        let summary = DeathSummary {
+            killer: "a Goblin".to_string(),
+            seed: 7,
+            turns: 12,
+            depth: 1,
+            level: 1,
+            xp: 0,
+            stats: vec![],
+            inventory: vec![],
+            last_messages: vec![],
+        };
+
+        let paths: Vec<String> = (0..3).map(|_| summary.write_in(dir).unwrap()).collect();
+        fs::remove_dir_all(dir).unwrap();
+
+        assert_ne!(paths[0], paths[1]);
+        assert_ne!(paths[1], paths[2]);
+        assert_ne!(paths[0], paths[2]);
+    }
+}
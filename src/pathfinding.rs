@@ -0,0 +1,225 @@
+use rltk::{Algorithm2D, BaseMap, DistanceAlg, Point, SmallVec};
+
+use crate::structs::{EntityWorld, InternalMapState, StatBlock, TileType};
+use crate::{EntityIndex, State};
+
+/*
+ * A borrowed view of the map used for a single A* query.
+ * Walls and tiles holding a living entity are blocked, except for the start and goal
+ * so that the searching entity and its target never block their own path.
+ */
+pub struct PathingMap<'a> {
+    map: &'a InternalMapState,
+    ecs: &'a EntityWorld,
+    start: usize,
+    goal: usize,
+}
+
+impl<'a> PathingMap<'a> {
+
+    pub fn new(map: &'a InternalMapState, ecs: &'a EntityWorld, start: (i32, i32), goal: (i32, i32)) -> Self {
+        PathingMap {
+            start: map.xy_idx(start.0, start.1),
+            goal: map.xy_idx(goal.0, goal.1),
+            map,
+            ecs,
+        }
+    }
+
+    fn is_occupied(&self, idx: usize) -> bool {
+        self.map.entities[idx].iter().any(|e| {
+            self.ecs.get::<StatBlock>(*e).map(|s| !s.dead).unwrap_or(false)
+        })
+    }
+
+    fn is_walkable(&self, x: i32, y: i32) -> bool {
+        if !self.map.in_bounds(Point::new(x, y)) {
+            return false;
+        }
+        let idx = self.map.xy_idx(x, y);
+        if let TileType::Wall(_) = self.map.tiles[idx].get() {
+            return false;
+        }
+        idx == self.start || idx == self.goal || !self.is_occupied(idx)
+    }
+
+    pub fn find_path(&self) -> Option<Vec<(i32, i32)>> {
+        let path = rltk::a_star_search(self.start, self.goal, self);
+        if !path.success {
+            return None;
+        }
+        // Steps include the starting tile
+        Some(path.steps.iter().skip(1).map(|idx| self.map.idx_xy(*idx)).collect())
+    }
+
+}
+
+impl BaseMap for PathingMap<'_> {
+    fn get_available_exits(&self, idx: usize) -> SmallVec<[(usize, f32); 10]> {
+        let mut exits = SmallVec::new();
+        let (x, y) = self.map.idx_xy(idx);
+
+        for (dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1), (-1, -1), (1, -1), (-1, 1), (1, 1)] {
+            let (nx, ny) = (x + dx, y + dy);
+            if !self.is_walkable(nx, ny) {
+                continue;
+            }
+            let cost = if dx != 0 && dy != 0 { 1.45 } else { 1.0 };
+            exits.push((self.map.xy_idx(nx, ny), cost));
+        }
+
+        exits
+    }
+
+    fn get_pathing_distance(&self, idx1: usize, idx2: usize) -> f32 {
+        let p1 = self.map.index_to_point2d(idx1);
+        let p2 = self.map.index_to_point2d(idx2);
+        DistanceAlg::Pythagoras.distance2d(p1, p2)
+    }
+}
+
+impl Algorithm2D for PathingMap<'_> {
+    fn dimensions(&self) -> Point {
+        self.map.dimensions()
+    }
+}
+
+/*
+ * The route an AI is currently walking. A target that moves a single tile is followed by extending
+ * the path, it is only recomputed when the target gets further away or the next step is blocked.
+ */
+#[derive(Default)]
+pub struct CachedPath {
+    pub steps: Vec<(i32, i32)>,
+    pub target: Option<(i32, i32)>,
+}
+
+impl CachedPath {
+
+    // Ends the path on the target again after it moved next to the old end
+    fn follow(&mut self, target: (i32, i32)) {
+        if self.target == Some(target) {
+            return;
+        }
+        self.target = Some(target);
+        match self.steps.iter().position(|step| *step == target) {
+            // Stepped back towards us, the rest of the path is no longer needed
+            Some(i) => self.steps.truncate(i + 1),
+            None => self.steps.push(target),
+        }
+    }
+
+    /*
+     * Moves the entity one step along its path towards target, planning a new path first if needed.
+     * Returns true if the entity moved.
+     */
+    pub fn step_towards(state: &mut State, me: EntityIndex, target: (i32, i32)) -> bool {
+        if state.ecs.get::<CachedPath>(me).is_err() {
+            state.ecs.insert_one(me, CachedPath::default()).expect("Failed to insert cached path");
+        }
+
+        let me_pos = state.ecs.get_entity_comp(me).pos();
+
+        let next_step = {
+            let mut cached = state.ecs.get_mut::<CachedPath>(me).unwrap();
+            let close = !cached.steps.is_empty()
+                && cached.target.is_some_and(|end| (end.0 - target.0).abs() <= 1 && (end.1 - target.1).abs() <= 1);
+            if close {
+                cached.follow(target);
+            }
+            let blocked = cached.steps.first().is_none_or(|step| {
+                !PathingMap::new(&state.map_state, &state.ecs, me_pos, target).is_walkable(step.0, step.1)
+            });
+            if !close || blocked { None } else { cached.steps.first().copied() }
+        };
+
+        let next_step = match next_step {
+            Some(step) => Some(step),
+            None => {
+                let steps = PathingMap::new(&state.map_state, &state.ecs, me_pos, target)
+                    .find_path()
+                    .unwrap_or_default();
+                let mut cached = state.ecs.get_mut::<CachedPath>(me).unwrap();
+                cached.target = Some(target);
+                cached.steps = steps;
+                cached.steps.first().copied()
+            }
+        };
+
+        let (nx, ny) = match next_step {
+            Some(step) => step,
+            None => return false,
+        };
+
+        let moved = state.move_entity_by(me, nx - me_pos.0, ny - me_pos.1) != (0, 0);
+        if moved {
+            state.ecs.get_mut::<CachedPath>(me).unwrap().steps.remove(0);
+        }
+        moved
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structs::map_utils::MapDescriptor;
+    use crate::tests::{entity_at, place, room, start, wall};
+
+    // A 7x6 room split by a wall at x = 3, with gaps at the given rows
+    fn split_room(gaps: &[i32]) -> MapDescriptor {
+        let mut map = room(7, 6);
+        for y in (1..5).filter(|y| !gaps.contains(y)) {
+            map.tiles[(y * map.width + 3) as usize] = wall();
+        }
+        map
+    }
+
+    fn path(state: &State, from: (i32, i32), to: (i32, i32)) -> Option<Vec<(i32, i32)>> {
+        PathingMap::new(&state.map_state, &state.ecs, from, to).find_path()
+    }
+
+    #[test]
+    fn paths_go_around_living_entities() {
+        let mut map = split_room(&[1, 4]);
+        place(&mut map, 3, 1, "Goblin");
+        let state = start(vec![map]);
+
+        let steps = path(&state, (1, 1), (5, 1)).unwrap();
+        assert!(steps.contains(&(3, 4)));
+        assert!(!steps.contains(&(3, 1)));
+        assert_eq!(steps.last(), Some(&(5, 1)));
+    }
+
+    #[test]
+    fn dead_entities_dont_block() {
+        let mut map = split_room(&[1, 4]);
+        place(&mut map, 3, 1, "Goblin");
+        let state = start(vec![map]);
+        let goblin = entity_at(&state, 3, 1).unwrap();
+        state.ecs.get_mut::<StatBlock>(goblin).unwrap().dead = true;
+
+        assert_eq!(path(&state, (1, 1), (5, 1)).unwrap(), vec![(2, 1), (3, 1), (4, 1), (5, 1)]);
+    }
+
+    #[test]
+    fn no_path_past_a_blocked_gap_but_the_goal_can_be_occupied() {
+        let mut map = split_room(&[2]);
+        place(&mut map, 3, 2, "Goblin");
+        let state = start(vec![map]);
+
+        assert!(path(&state, (1, 1), (5, 1)).is_none());
+        assert_eq!(path(&state, (1, 1), (3, 2)).unwrap().last(), Some(&(3, 2)));
+    }
+
+    #[test]
+    fn a_target_that_steps_aside_extends_the_path() {
+        let mut cached = CachedPath { steps: vec![(2, 1), (3, 1)], target: Some((3, 1)) };
+        cached.follow((4, 2));
+        assert_eq!(cached.steps, vec![(2, 1), (3, 1), (4, 2)]);
+
+        cached.follow((2, 1));
+        assert_eq!(cached.steps, vec![(2, 1)]);
+        assert_eq!(cached.target, Some((2, 1)));
+    }
+}
@@ -0,0 +1,176 @@
+use rltk::{ColorPair, DrawBatch, Point, Rltk, VirtualKeyCode, RGB};
+
+use crate::keybindings::Command;
+use crate::loot::StatKind;
+use crate::status::StatusEffects;
+use crate::structs::{Container, EntityStat, Equipment, StatBlock, UInterface};
+use crate::{EntityIndex, State};
+
+// What one level up can be spent on
+const HP_PER_LEVEL: i32 = 5;
+const ATK_PER_LEVEL: i32 = 1;
+const DEF_PER_LEVEL: i32 = 1;
+
+// Total xp needed to be level, 20 for level 2, 60 for 3, 120 for 4...
+pub fn xp_to_reach(level: i32) -> i32 {
+    10 * level * (level - 1)
+}
+
+// Tougher monsters are worth more
+pub fn kill_xp(stats: &StatBlock) -> i32 {
+    stats.hp.get_max() + 2 * (stats.atk.get_total() + stats.def.get_total())
+}
+
+// Gives the player the xp dead was worth
+pub fn award_kill(state: &mut State, dead: EntityIndex) {
+    let xp = match state.ecs.get::<StatBlock>(dead) {
+        Ok(stats) => kill_xp(&stats),
+        Err(_) => return,
+    };
+    gain_xp(state, xp);
+}
+
+pub fn gain_xp(state: &mut State, amount: i32) {
+    let (levels, level) = {
+        let mut stats = state.get_player_stat_block();
+        stats.xp += amount;
+        let mut levels = 0;
+        while stats.xp >= xp_to_reach(stats.level + 1) {
+            stats.level += 1;
+            stats.unspent_levels += 1;
+            levels += 1;
+        }
+        (levels, stats.level)
+    };
+
+    state.log.push(format!("You gain {} xp", amount), rltk::GREY);
+    if levels > 0 {
+        let key = state.keys.keys_for(Command::CharacterSheet)
+            .first()
+            .map(|k| format!(", press {:?} to grow stronger", k))
+            .unwrap_or_default();
+        state.log.push(format!("You reached level {}{}", level, key), rltk::YELLOW);
+    }
+}
+
+// Spends one level up on stat, false if there are none to spend
+pub fn spend_level(state: &mut State, stat: StatKind) -> bool {
+    let mut stats = state.get_player_stat_block();
+    if stats.unspent_levels <= 0 {
+        return false;
+    }
+    stats.unspent_levels -= 1;
+    match stat {
+        StatKind::Hp => stats.hp.raise(HP_PER_LEVEL),
+        StatKind::Atk => stats.atk.raise(ATK_PER_LEVEL),
+        StatKind::Def => stats.def.raise(DEF_PER_LEVEL),
+    }
+    true
+}
+
+fn stat_row(label: &str, stat: &EntityStat) -> String {
+    format!("{:<5}{:>4} (base {}, max {}, bonus {:+})", label, stat.get_total(), stat.get_base(), stat.get_max(), stat.get_bonus())
+}
+
+/*
+ * The player's level, stats, equipment and status, and where level ups are spent.
+ */
+#[derive(Default)]
+pub struct CharacterSheetUI;
+
+impl UInterface for CharacterSheetUI {
+    fn on_input(&self, state: &mut State, key: Option<VirtualKeyCode>) -> bool {
+        let key = match key {
+            Some(key) => key,
+            None => return false,
+        };
+        match key {
+            VirtualKeyCode::Key1 => { spend_level(state, StatKind::Hp); }
+            VirtualKeyCode::Key2 => { spend_level(state, StatKind::Atk); }
+            VirtualKeyCode::Key3 => { spend_level(state, StatKind::Def); }
+            VirtualKeyCode::Escape => return true,
+            _ => return state.keys.command_for(key) == Some(Command::CharacterSheet),
+        }
+        false
+    }
+
+    fn render(&self, ctx: &mut Rltk, state: &State) {
+        let mut g_db = DrawBatch::new();
+        g_db.cls();
+        let white = ColorPair::new(RGB::named(rltk::WHITE), RGB::named(rltk::BLACK));
+        let yellow = ColorPair::new(RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK));
+
+        let player = state.ecs.get_player_id();
+        let stats = state.get_player_stat_block().clone();
+
+        g_db.print_color(Point::new(0, 0), "Character sheet (Esc to close)", white);
+        g_db.print_color(
+            Point::new(1, 2),
+            format!("Level {}   XP {} / {}", stats.level, stats.xp, xp_to_reach(stats.level + 1)),
+            yellow,
+        );
+        g_db.print_color(Point::new(1, 4), stat_row("HP", &stats.hp), white);
+        g_db.print_color(Point::new(1, 5), stat_row("ATK", &stats.atk), white);
+        g_db.print_color(Point::new(1, 6), stat_row("DEF", &stats.def), white);
+
+        if stats.unspent_levels > 0 {
+            g_db.print_color(Point::new(1, 8), format!("Level ups to spend: {}", stats.unspent_levels), yellow);
+            g_db.print_color(
+                Point::new(1, 9),
+                format!("1: +{} max HP   2: +{} ATK   3: +{} DEF", HP_PER_LEVEL, ATK_PER_LEVEL, DEF_PER_LEVEL),
+                yellow,
+            );
+        }
+
+        g_db.print_color(Point::new(1, 11), "Equipment", white);
+        if let (Ok(equipment), Ok(container)) = (state.ecs.get::<Equipment>(player), state.ecs.get::<Container>(player)) {
+            for (i, equipped) in equipment.equips.iter().enumerate() {
+                let name = equipped
+                    .and_then(|index| container.items.get(index))
+                    .map(|item| item.label())
+                    .unwrap_or_else(|| "Empty".to_string());
+                g_db.print_color(Point::new(2, 12 + i as i32), format!("{:<8}{}", equipment.slot_kind(i).name(), name), white);
+            }
+        }
+
+        if let Ok(status) = state.ecs.get::<StatusEffects>(player) {
+            g_db.print_color(Point::new(1, 18), "Status", white);
+            for (i, effect) in status.effects.iter().enumerate() {
+                g_db.print_color(
+                    Point::new(2, 19 + i as i32),
+                    format!("{} for {} turns", effect.kind.title(), effect.turns),
+                    ColorPair::new(RGB::named(effect.kind.color()), RGB::named(rltk::BLACK)),
+                );
+            }
+        }
+
+        g_db.submit(0).expect("Rendering error with draw batch");
+
+        rltk::render_draw_buffer(ctx).expect("Rendering error");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_level_costs_more_than_the_last() {
+        assert_eq!(xp_to_reach(1), 0);
+        assert_eq!((xp_to_reach(2), xp_to_reach(3), xp_to_reach(4)), (20, 60, 120));
+        for level in 2..20 {
+            assert!(xp_to_reach(level + 1) - xp_to_reach(level) > xp_to_reach(level) - xp_to_reach(level - 1));
+        }
+    }
+
+    #[test]
+    fn tougher_monsters_are_worth_more() {
+        let stats = |hp, atk| StatBlock {
+            hp: EntityStat::new("Hit Points", hp),
+            atk: EntityStat::new("Attack", atk),
+            ..Default::default()
+        };
+        assert!(kill_xp(&stats(20, 1)) > kill_xp(&stats(10, 1)));
+        assert!(kill_xp(&stats(10, 5)) > kill_xp(&stats(10, 1)));
+    }
+}
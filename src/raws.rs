@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::scheduler;
+use crate::structs::{Display, EffectLink, EquipSlot, Item, StatBonuses};
+use crate::targeting::Targeting;
+
+#[derive(Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum AIKind {
+    // Chases and attacks the player once it is seen
+    Zombie,
+    // Never acts on its own
+    Passive,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RawStats {
+    pub hp: i32,
+    pub atk: i32,
+    #[serde(default)]
+    pub def: i32,
+}
+
+fn default_vision() -> i32 {
+    8
+}
+
+/*
+ * A single monster definition as written in the raws file.
+ * `key` is the name maps and the editor refer to, `name` is what the player sees.
+ */
+#[derive(Clone, Serialize, Deserialize)]
+pub struct MonsterRaw {
+    pub key: String,
+    pub name: String,
+    pub glyph: char,
+    pub fg: (u8, u8, u8),
+    pub bg: (u8, u8, u8),
+    pub stats: RawStats,
+    pub ai: AIKind,
+    pub art: String,
+    #[serde(default = "default_vision")]
+    pub vision: i32,
+    #[serde(default = "scheduler::default_speed")]
+    pub speed: i32,
+    // Item keys the monster carries and drops on death
+    #[serde(default)]
+    pub loot: Vec<String>,
+    // Key of the loot table rolled on death
+    #[serde(default)]
+    pub loot_table: Option<String>,
+    // Effect landed by every attack on top of the damage
+    #[serde(default)]
+    pub on_hit: EffectLink,
+}
+
+impl MonsterRaw {
+    pub fn display(&self) -> Display {
+        Display {
+            glyph: rltk::to_cp437(self.glyph),
+            fg: self.fg,
+            bg: self.bg,
+        }
+    }
+}
+
+fn load_raws<T: DeserializeOwned>(file_name: &str, kind: &str) -> Result<Vec<T>, String> {
+    let mut contents = String::new();
+    File::open(file_name)
+        .and_then(|mut f| f.read_to_string(&mut contents))
+        .map_err(|e| format!("Could not read the {} raws {}: {}", kind, file_name, e))?;
+    serde_json::from_str(&contents)
+        .map_err(|e| format!("Malformed {} raws in {}: {}", kind, file_name, e))
+}
+
+#[derive(Default)]
+pub struct MonsterRegistry {
+    monsters: HashMap<String, MonsterRaw>,
+}
+
+impl MonsterRegistry {
+
+    pub fn load_from_file(file_name: &str) -> Result<MonsterRegistry, String> {
+        let raws: Vec<MonsterRaw> = load_raws(file_name, "monster")?;
+
+        Ok(MonsterRegistry {
+            monsters: raws.into_iter().map(|r| (r.key.clone(), r)).collect(),
+        })
+    }
+
+    pub fn get(&self, key: &str) -> Option<&MonsterRaw> {
+        self.monsters.get(key)
+    }
+
+}
+
+fn default_item_bg() -> (u8, u8, u8) {
+    rltk::BLACK
+}
+
+/*
+ * A single item definition as written in the raws file, maps and monster loot refer to it by `key`.
+ */
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ItemRaw {
+    pub key: String,
+    pub name: String,
+    pub glyph: char,
+    pub fg: (u8, u8, u8),
+    #[serde(default = "default_item_bg")]
+    pub bg: (u8, u8, u8),
+    pub art: String,
+    #[serde(default)]
+    pub effect: EffectLink,
+    #[serde(default = "scheduler::default_action_cost")]
+    pub time_cost: i32,
+    #[serde(default)]
+    pub targeting: Targeting,
+    // Consumables set how many uses they have
+    #[serde(default)]
+    pub charges: Option<i32>,
+    #[serde(default)]
+    pub slot: EquipSlot,
+    #[serde(default)]
+    pub bonuses: StatBonuses,
+}
+
+impl ItemRaw {
+    pub fn display(&self) -> Display {
+        Display {
+            glyph: rltk::to_cp437(self.glyph),
+            fg: self.fg,
+            bg: self.bg,
+        }
+    }
+
+    pub fn to_item(&self) -> Item {
+        Item {
+            name: self.name.clone(),
+            art: self.art.clone(),
+            d: self.display(),
+            effect_chain: self.effect.clone(),
+            time_cost: self.time_cost,
+            targeting: self.targeting,
+            charges: self.charges,
+            slot: self.slot,
+            bonuses: self.bonuses,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct ItemRegistry {
+    items: HashMap<String, ItemRaw>,
+}
+
+impl ItemRegistry {
+
+    pub fn load_from_file(file_name: &str) -> Result<ItemRegistry, String> {
+        let raws: Vec<ItemRaw> = load_raws(file_name, "item")?;
+
+        Ok(ItemRegistry {
+            items: raws.into_iter().map(|r| (r.key.clone(), r)).collect(),
+        })
+    }
+
+    pub fn get(&self, key: &str) -> Option<&ItemRaw> {
+        self.items.get(key)
+    }
+
+}
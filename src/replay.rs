@@ -0,0 +1,183 @@
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::Write;
+
+use rltk::{Rltk, VirtualKeyCode};
+use serde::{Deserialize, Serialize};
+
+use crate::keybindings::Binding;
+use crate::mapgen::MapSource;
+use crate::structs::StatBlock;
+
+// Delay between replayed inputs so a replay can be watched
+const REPLAY_STEP_MS: f32 = 120.0;
+
+/*
+ * The input the game reacts to on one frame, either read from the window or from a replay.
+ * Clicks are in console cells.
+ */
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct InputFrame {
+    pub key: Option<VirtualKeyCode>,
+    pub click: Option<(i32, i32)>,
+}
+
+impl InputFrame {
+    pub fn from_ctx(ctx: &Rltk) -> Self {
+        InputFrame {
+            key: ctx.key,
+            click: if ctx.left_click { Some(ctx.mouse_pos()) } else { None },
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.key.is_none() && self.click.is_none()
+    }
+}
+
+/*
+ * Everything a new game depends on besides the code and the raws,
+ * followed by every input given to it in order.
+ * On disk the first line is the replay without its inputs, then every input is a line of its own.
+ */
+#[derive(Serialize, Deserialize)]
+pub struct Replay {
+    pub seed: u64,
+    pub destinations: Vec<MapSource>,
+    pub player_stats: StatBlock,
+    // Keys are recorded raw, so they have to mean the same thing on playback
+    #[serde(default)]
+    pub keybindings: Option<Vec<Binding>>,
+    #[serde(skip)]
+    pub inputs: Vec<InputFrame>,
+}
+
+impl Replay {
+    pub fn load_from_file(file_name: &str) -> Result<Replay, String> {
+        let contents = fs::read_to_string(file_name)
+            .map_err(|e| format!("Could not read replay {}: {}", file_name, e))?;
+        let lines = contents.lines().filter(|l| !l.trim().is_empty()).collect::<Vec<_>>();
+        let header = lines.first().ok_or_else(|| format!("Replay {} is empty", file_name))?;
+        let mut replay: Replay = serde_json::from_str(header)
+            .map_err(|e| format!("Malformed replay {}: {}", file_name, e))?;
+
+        for (i, line) in lines.iter().enumerate().skip(1) {
+            match serde_json::from_str(line) {
+                Ok(input) => replay.inputs.push(input),
+                // A crash can cut the last input off halfway
+                Err(_) if i == lines.len() - 1 => break,
+                Err(e) => return Err(format!("Malformed input on line {} of replay {}: {}", i + 1, file_name, e)),
+            }
+        }
+        Ok(replay)
+    }
+}
+
+pub struct InputRecorder {
+    file_name: String,
+    file: Option<File>,
+}
+
+impl InputRecorder {
+
+    pub fn new(file_name: &str, seed: u64, destinations: Vec<MapSource>, player_stats: StatBlock, keybindings: Vec<Binding>) -> Self {
+        let header = Replay {
+            seed,
+            destinations,
+            player_stats,
+            keybindings: Some(keybindings),
+            inputs: vec![],
+        };
+        let file = serde_json::to_string(&header)
+            .map_err(|e| e.to_string())
+            .and_then(|s| {
+                File::create(file_name)
+                    .and_then(|mut f| writeln!(f, "{}", s).map(|_| f))
+                    .map_err(|e| e.to_string())
+            });
+        if let Err(e) = &file {
+            eprintln!("Failed to write replay {}: {}", file_name, e);
+        }
+        InputRecorder {
+            file_name: file_name.to_string(),
+            file: file.ok(),
+        }
+    }
+
+    // Appends the input right away so a crash still leaves a usable replay
+    pub fn record(&mut self, input: &InputFrame) {
+        if input.is_empty() {
+            return;
+        }
+        let file = match self.file.as_mut() {
+            Some(file) => file,
+            None => return,
+        };
+
+        let written = serde_json::to_string(input)
+            .map_err(|e| e.to_string())
+            .and_then(|s| writeln!(file, "{}", s).map_err(|e| e.to_string()));
+        if let Err(e) = written {
+            eprintln!("Failed to write replay {}: {}", self.file_name, e);
+            self.file = None;
+        }
+    }
+
+}
+
+pub struct ReplayPlayer {
+    inputs: VecDeque<InputFrame>,
+    until_next: f32,
+}
+
+impl ReplayPlayer {
+
+    pub fn new(inputs: Vec<InputFrame>) -> Self {
+        ReplayPlayer {
+            inputs: inputs.into(),
+            until_next: REPLAY_STEP_MS,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.inputs.is_empty()
+    }
+
+    // The next recorded input once enough time passed, an empty frame otherwise
+    pub fn next_input(&mut self, frame_time_ms: f32) -> InputFrame {
+        self.until_next -= frame_time_ms;
+        if self.until_next > 0.0 {
+            return InputFrame::default();
+        }
+        self.until_next = REPLAY_STEP_MS;
+        self.inputs.pop_front().unwrap_or_default()
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::OpenOptions;
+
+    use super::*;
+
+    #[test]
+    fn a_cut_off_last_input_is_dropped() {
+        let file_name = std::env::temp_dir().join(format!("replay_cut_off_{}.json", std::process::id()));
+        let file_name = file_name.to_str().unwrap();
+
+        let mut recorder = InputRecorder::new(file_name, 3, vec![], StatBlock::default(), vec![]);
+        recorder.record(&InputFrame { key: Some(VirtualKeyCode::Numpad6), click: None });
+        recorder.record(&InputFrame::default());
+        recorder.record(&InputFrame { key: None, click: Some((4, 5)) });
+        let mut file = OpenOptions::new().append(true).open(file_name).unwrap();
+        write!(file, "{{\"key\":\"Num").unwrap();
+
+        let replay = Replay::load_from_file(file_name).unwrap();
+        let _ = fs::remove_file(file_name);
+        assert_eq!(replay.seed, 3);
+        assert_eq!(replay.inputs.len(), 2);
+        assert_eq!(replay.inputs[0].key, Some(VirtualKeyCode::Numpad6));
+        assert_eq!(replay.inputs[1].click, Some((4, 5)));
+    }
+}
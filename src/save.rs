@@ -0,0 +1,204 @@
+use std::fs::{self, File};
+use std::io::{Read, Write};
+
+use serde::{Deserialize, Serialize};
+
+use crate::items::FloorItem;
+use crate::loot::LootDrop;
+use crate::mapgen::MapSource;
+use crate::math_utils::GameRng;
+use crate::scheduler::{self, Initiative};
+use crate::status::StatusEffects;
+use crate::structs::*;
+use crate::{EntityIndex, State};
+
+pub const SAVE_DIR: &str = "saves";
+
+pub fn save_path(slot: usize) -> String {
+    format!("{}/slot_{}.json", SAVE_DIR, slot)
+}
+
+// A dead character can't be continued, so their save goes with them
+pub fn delete_slot(slot: usize) -> Result<(), String> {
+    match fs::remove_file(save_path(slot)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.to_string()),
+        _ => Ok(()),
+    }
+}
+
+/*
+ * Every component that survives a save, one optional field per component.
+ * Components not listed here (cached paths, visible tiles) are rebuilt after loading.
+ */
+#[derive(Default, Serialize, Deserialize)]
+pub struct SavedEntity {
+    pub basic: Option<BasicEntity>,
+    pub stats: Option<StatBlock>,
+    pub view: Option<EntityView>,
+    pub container: Option<Container>,
+    pub equipment: Option<Equipment>,
+    pub self_destruct: Option<SelfDestructAI>,
+    // Only the range is kept, the visible tiles are recomputed
+    pub viewshed: Option<i32>,
+    #[serde(default)]
+    pub initiative: Option<Initiative>,
+    #[serde(default)]
+    pub floor_item: Option<FloorItem>,
+    #[serde(default)]
+    pub loot_drop: Option<LootDrop>,
+    #[serde(default)]
+    pub status: Option<StatusEffects>,
+    #[serde(default)]
+    pub on_hit: Option<OnHit>,
+
+    pub player: bool,
+    pub player_ai: bool,
+    pub zombie_ai: bool,
+}
+
+impl SavedEntity {
+
+    pub fn from_world(world: &EntityWorld, e: EntityIndex) -> Self {
+        SavedEntity {
+            basic: world.get::<BasicEntity>(e).ok().map(|c| (*c).clone()),
+            stats: world.get::<StatBlock>(e).ok().map(|c| (*c).clone()),
+            view: world.get::<EntityView>(e).ok().map(|c| (*c).clone()),
+            container: world.get::<Container>(e).ok().map(|c| (*c).clone()),
+            equipment: world.get::<Equipment>(e).ok().map(|c| (*c).clone()),
+            self_destruct: world.get::<SelfDestructAI>(e).ok().map(|c| (*c).clone()),
+            viewshed: world.get::<Viewshed>(e).ok().map(|c| c.range),
+            initiative: world.get::<Initiative>(e).ok().map(|c| (*c).clone()),
+            floor_item: world.get::<FloorItem>(e).ok().map(|c| (*c).clone()),
+            loot_drop: world.get::<LootDrop>(e).ok().map(|c| (*c).clone()),
+            status: world.get::<StatusEffects>(e).ok().map(|c| (*c).clone()),
+            on_hit: world.get::<OnHit>(e).ok().map(|c| (*c).clone()),
+
+            player: world.get::<Player>(e).is_ok(),
+            player_ai: world.get::<PlayerAI>(e).is_ok(),
+            zombie_ai: world.get::<ZombieAI>(e).is_ok(),
+        }
+    }
+
+    pub fn spawn(self, world: &mut EntityWorld) -> EntityIndex {
+        let mut builder = hecs::EntityBuilder::new();
+
+        if let Some(c) = self.basic { builder.add(c); }
+        if let Some(c) = self.stats { builder.add(c); }
+        if let Some(c) = self.view { builder.add(c); }
+        if let Some(c) = self.container { builder.add(c); }
+        if let Some(c) = self.equipment { builder.add(c); }
+        if let Some(c) = self.self_destruct { builder.add(c); }
+        if let Some(range) = self.viewshed { builder.add(Viewshed::new(range)); }
+        if let Some(c) = self.floor_item { builder.add(c); }
+        if let Some(c) = self.loot_drop { builder.add(c); }
+        if let Some(c) = self.status { builder.add(c); }
+        if let Some(c) = self.on_hit { builder.add(c); }
+        match self.initiative {
+            Some(c) => { builder.add(c); }
+            // Saves from before the scheduler still need their actors to act
+            None if self.player || self.zombie_ai => { builder.add(Initiative::new(scheduler::NORMAL_SPEED)); }
+            None => {}
+        }
+
+        if self.player { builder.add(Player); }
+        if self.player_ai { builder.add(PlayerAI); }
+        if self.zombie_ai { builder.add(ZombieAI); }
+
+        world.spawn(builder.build())
+    }
+
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SaveGame {
+    // The seed the run was started with and where the generator continues from
+    #[serde(default = "GameRng::from_entropy")]
+    pub rng: GameRng,
+
+    // How long the run has gone on, for the morgue file
+    #[serde(default)]
+    pub game_ticks: u64,
+
+    pub current_map: usize,
+    pub portal_locations: Vec<MapSource>,
+
+    pub map_width: i32,
+    pub map_height: i32,
+    pub tiles: Vec<TileType>,
+    pub revealed: Vec<bool>,
+
+    pub entities: Vec<SavedEntity>,
+}
+
+impl SaveGame {
+
+    pub fn from_state(state: &State) -> Self {
+        let map = &state.map_state;
+        let entities = state.ecs
+            .iter()
+            .map(|e| SavedEntity::from_world(&state.ecs, e.entity()))
+            .collect();
+
+        SaveGame {
+            rng: state.rng.clone(),
+            game_ticks: state.game_ticks,
+            current_map: map.current_map,
+            portal_locations: map.portal_locations.clone(),
+            map_width: map.map_width,
+            map_height: map.map_height,
+            tiles: map.tiles.iter().map(|t| t.get()).collect(),
+            revealed: map.revealed.clone(),
+            entities,
+        }
+    }
+
+    pub fn write_to_slot(&self, slot: usize) -> Result<(), String> {
+        fs::create_dir_all(SAVE_DIR).map_err(|e| e.to_string())?;
+        let string_buf = serde_json::to_string(self).map_err(|e| e.to_string())?;
+        let mut file = File::create(save_path(slot)).map_err(|e| e.to_string())?;
+        file.write_all(string_buf.as_bytes()).map_err(|e| e.to_string())
+    }
+
+    pub fn read_from_slot(slot: usize) -> Result<Self, String> {
+        let mut file = File::open(save_path(slot))
+            .map_err(|e| format!("Could not open {}: {}", save_path(slot), e))?;
+        let mut string_buf = String::new();
+        file.read_to_string(&mut string_buf).map_err(|e| e.to_string())?;
+        serde_json::from_str(&string_buf)
+            .map_err(|e| format!("Corrupt save {}: {}", save_path(slot), e))
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actions::Action;
+    use crate::tests::{entity_at, hp_of, place, player_pos, room, start};
+
+    #[test]
+    fn a_loaded_world_matches_the_saved_one() {
+        let mut map = room(10, 8);
+        place(&mut map, 2, 1, "Ember");
+        place(&mut map, 7, 6, "Goblin");
+        let mut state = start(vec![map]);
+
+        let goblin = entity_at(&state, 7, 6).unwrap();
+        state.ecs.get_mut::<StatBlock>(goblin).unwrap().hp.decrement(2);
+        state.perform(Action::Move(1, 0));
+        state.perform(Action::PickUp);
+        state.perform(Action::Move(1, 1));
+
+        let json = serde_json::to_string(&SaveGame::from_state(&state)).unwrap();
+        let mut loaded = State::from_save(serde_json::from_str(&json).unwrap(), 0).unwrap();
+
+        assert_eq!(serde_json::to_string(&SaveGame::from_state(&loaded)).unwrap(), json);
+        assert_eq!(player_pos(&loaded), player_pos(&state));
+        let goblin_pos = state.ecs.get_entity_comp(goblin).pos();
+        let loaded_goblin = entity_at(&loaded, goblin_pos.0, goblin_pos.1).unwrap();
+        assert_eq!(hp_of(&loaded, loaded_goblin), hp_of(&state, goblin));
+        assert_eq!(loaded.map_state.revealed, state.map_state.revealed);
+        assert_eq!(loaded.map_state.visible, state.map_state.visible);
+        assert_eq!(loaded.rng.rng().next_u64(), state.rng.rng().next_u64());
+    }
+}
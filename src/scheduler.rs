@@ -0,0 +1,90 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{EntityIndex, State};
+
+// Energy an ordinary action costs
+pub const ACTION_COST: i32 = 100;
+// Speed of the player and of any monster without a speed of its own
+pub const NORMAL_SPEED: i32 = 10;
+// Ticks that make up one "turn" of a normal speed actor
+pub const TICKS_PER_TURN: u64 = (ACTION_COST / NORMAL_SPEED) as u64;
+
+pub fn default_speed() -> i32 {
+    NORMAL_SPEED
+}
+
+pub fn default_action_cost() -> i32 {
+    ACTION_COST
+}
+
+/*
+ * Every tick each actor gains speed energy, an actor may act once its energy is back to zero or above
+ * and acting spends the cost of the action. A speed 20 spider therefore acts twice per player move,
+ * a speed 5 rock every other one.
+ */
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Initiative {
+    pub speed: i32,
+    pub energy: i32,
+}
+
+impl Initiative {
+
+    pub fn new(speed: i32) -> Self {
+        Initiative {
+            speed,
+            energy: 0,
+        }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.energy >= 0
+    }
+
+    pub fn spend(state: &mut State, me: EntityIndex, cost: i32) {
+        if let Ok(mut init) = state.ecs.get_mut::<Initiative>(me) {
+            init.energy -= cost;
+        }
+    }
+
+    pub fn ready(state: &State, me: EntityIndex) -> bool {
+        state.ecs.get::<Initiative>(me).map(|i| i.is_ready()).unwrap_or(true)
+    }
+
+    // Gives every actor its speed worth of energy
+    pub fn tick_all(state: &mut State) {
+        for (_, init) in state.ecs.query_mut::<&mut Initiative>() {
+            init.energy += init.speed;
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::actions::Action;
+    use crate::gamelog;
+    use crate::tests::{entity_at, place, room, start};
+
+    #[test]
+    fn faster_monsters_act_more_often() {
+        let mut map = room(6, 6);
+        place(&mut map, 2, 1, "Spider");
+        place(&mut map, 1, 2, "Goblin");
+        place(&mut map, 2, 2, "Rock");
+        let mut state = start(vec![map]);
+        let monsters = [entity_at(&state, 2, 1), entity_at(&state, 1, 2), entity_at(&state, 2, 2)];
+
+        for _ in 0..4 {
+            state.perform(Action::Wait);
+        }
+
+        // Everyone next to the player attacks on each of their turns
+        let hits = monsters.map(|e| {
+            let attack = format!("{} hits you", gamelog::describe(&state, e.unwrap()));
+            state.log.entries().iter().filter(|l| l.text.starts_with(&attack)).count()
+        });
+        // All of them act on the first tick, after that it's down to speed
+        assert_eq!(hits, [1 + 8, 1 + 4, 1 + 2]);
+    }
+}
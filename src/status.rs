@@ -0,0 +1,194 @@
+use rltk::TextBuilder;
+use serde::{Deserialize, Serialize};
+
+use crate::gamelog;
+use crate::structs::{Player, StatBlock};
+use crate::{EntityIndex, State};
+
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum StatusKind {
+    // Loses potency hp every turn, doses add up
+    Poison,
+    // Loses potency hp every turn, catching fire again only fans the flames
+    Burning,
+    // Loses its next turns instead of acting
+    Stun,
+    // Gains potency hp every turn
+    Regen,
+}
+
+impl StatusKind {
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            StatusKind::Poison => "poisoned",
+            StatusKind::Burning => "burning",
+            StatusKind::Stun => "stunned",
+            StatusKind::Regen => "regenerating",
+        }
+    }
+
+    // How it reads in the stat panel
+    pub fn title(&self) -> &'static str {
+        match self {
+            StatusKind::Poison => "Poisoned",
+            StatusKind::Burning => "Burning",
+            StatusKind::Stun => "Stunned",
+            StatusKind::Regen => "Regenerating",
+        }
+    }
+
+    pub fn color(&self) -> (u8, u8, u8) {
+        match self {
+            StatusKind::Poison => rltk::GREEN,
+            StatusKind::Burning => rltk::ORANGE,
+            StatusKind::Stun => rltk::YELLOW,
+            StatusKind::Regen => rltk::PINK,
+        }
+    }
+
+}
+
+fn default_potency() -> i32 {
+    1
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct StatusEffect {
+    pub kind: StatusKind,
+    // Turns left, for stuns the number of actions lost
+    pub turns: i32,
+    #[serde(default = "default_potency")]
+    pub potency: i32,
+}
+
+impl StatusEffect {
+
+    // Folds another dose of the same kind into this one
+    fn stack(&mut self, other: &StatusEffect) {
+        match self.kind {
+            StatusKind::Poison => {
+                self.potency += other.potency;
+                self.turns = self.turns.max(other.turns);
+            }
+            StatusKind::Stun => {
+                self.turns += other.turns;
+            }
+            StatusKind::Burning | StatusKind::Regen => {
+                self.potency = self.potency.max(other.potency);
+                self.turns = self.turns.max(other.turns);
+            }
+        }
+    }
+
+}
+
+/*
+ * The timed effects on an entity, at most one per kind since doses of a kind stack into each other.
+ */
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct StatusEffects {
+    pub effects: Vec<StatusEffect>,
+}
+
+impl StatusEffects {
+
+    pub fn make_text_builder(&self, builder: &mut TextBuilder) {
+        for effect in &self.effects {
+            let turns = if effect.turns == 1 { "turn" } else { "turns" };
+            let line = match effect.kind {
+                StatusKind::Stun => format!("{} ({} {})", effect.kind.title(), effect.turns, turns),
+                kind => format!("{} {} ({} {})", kind.title(), effect.potency, effect.turns, turns),
+            };
+            builder.fg(rltk::RGB::named(effect.kind.color())).append(line.as_str()).ln();
+        }
+        builder.fg(rltk::RGB::named(rltk::WHITE));
+    }
+
+}
+
+fn is_are(state: &State, e: EntityIndex) -> &'static str {
+    if state.ecs.get::<Player>(e).is_ok() { "are" } else { "is" }
+}
+
+// Gives target the effect, stacking it with any of the same kind it already has
+pub fn apply(state: &mut State, target: EntityIndex, effect: &StatusEffect) {
+    match state.ecs.get::<StatBlock>(target) {
+        Ok(stats) if !stats.dead => {}
+        _ => return,
+    }
+
+    let has_effects = state.ecs.get::<StatusEffects>(target).is_ok();
+    if !has_effects {
+        state.ecs.insert_one(target, StatusEffects::default()).expect("Failed to insert status effects");
+    }
+    {
+        let mut status = state.ecs.get_mut::<StatusEffects>(target).unwrap();
+        match status.effects.iter_mut().find(|e| e.kind == effect.kind) {
+            Some(existing) => existing.stack(effect),
+            None => status.effects.push(effect.clone()),
+        }
+    }
+
+    let msg = format!("{} {} {}", gamelog::describe(state, target), is_are(state, target), effect.kind.name());
+    state.log.push(msg, effect.kind.color());
+}
+
+// Runs every effect on me for one turn and drops the ones that ran out, stuns only wear off by losing turns
+pub fn on_turn(state: &mut State, me: EntityIndex) {
+    let effects = match state.ecs.get::<StatusEffects>(me) {
+        Ok(status) => status.effects.clone(),
+        Err(_) => return,
+    };
+    if state.ecs.get::<StatBlock>(me).map(|s| s.dead).unwrap_or(true) {
+        return;
+    }
+
+    for effect in &effects {
+        let mut stats = state.ecs.get_mut::<StatBlock>(me).unwrap();
+        match effect.kind {
+            StatusKind::Poison | StatusKind::Burning => {
+                stats.hp.decrement(effect.potency);
+                drop(stats);
+                let source = if effect.kind == StatusKind::Poison { "poison" } else { "fire" };
+                state.note_hit(me, source.to_string());
+            }
+            StatusKind::Regen => stats.hp.increment(effect.potency),
+            StatusKind::Stun => {}
+        }
+    }
+
+    let expired = {
+        let mut status = state.ecs.get_mut::<StatusEffects>(me).unwrap();
+        for effect in status.effects.iter_mut().filter(|e| e.kind != StatusKind::Stun) {
+            effect.turns -= 1;
+        }
+        let expired = status.effects.iter().filter(|e| e.turns <= 0).map(|e| e.kind).collect::<Vec<_>>();
+        status.effects.retain(|e| e.turns > 0);
+        expired
+    };
+    for kind in expired {
+        let msg = format!("{} {} no longer {}", gamelog::describe(state, me), is_are(state, me), kind.name());
+        state.log.push(msg, rltk::GREY);
+    }
+}
+
+// Whether me is stunned out of the action it was about to take, which uses up one turn of the stun
+pub fn lose_turn(state: &mut State, me: EntityIndex) -> bool {
+    let stunned = match state.ecs.get_mut::<StatusEffects>(me) {
+        Ok(mut status) => match status.effects.iter_mut().find(|e| e.kind == StatusKind::Stun) {
+            Some(stun) => {
+                stun.turns -= 1;
+                status.effects.retain(|e| e.turns > 0);
+                true
+            }
+            None => false,
+        },
+        Err(_) => false,
+    };
+    if stunned {
+        let msg = format!("{} {} too stunned to act", gamelog::describe(state, me), is_are(state, me));
+        state.log.push(msg, StatusKind::Stun.color());
+    }
+    stunned
+}
@@ -1,14 +1,18 @@
-use crate::{EntityIndex, State, math_utils};
+use crate::{EntityIndex, State};
+use crate::pathfinding::CachedPath;
+use crate::gamelog;
+use crate::mapgen::MapSource;
+use crate::targeting::{Targeting, Targets};
+use crate::status::StatusEffect;
 
 use std::cell::{Cell, Ref, RefCell, RefMut};
 use std::cmp::{max, min};
 use std::collections::HashSet;
 use std::f32::consts::SQRT_2;
 use std::ops::{Deref, DerefMut};
-use std::sync::Arc;
 
 use hecs::World;
-use rltk::{DrawBatch, Point, RGB, Rltk, TextBuilder, VirtualKeyCode, XpFile};
+use rltk::{Algorithm2D, BaseMap, DrawBatch, Point, RGB, Rltk, TextBuilder, VirtualKeyCode};
 use serde::{Deserialize, Serialize};
 
 pub mod map_utils {
@@ -19,15 +23,18 @@ pub mod map_utils {
 
     use super::TileType;
 
-    #[derive(Serialize, Deserialize)]
+    #[derive(Clone, Serialize, Deserialize)]
     pub struct MapDescriptor {
         pub width: i32,
         pub height: i32,
         pub tiles: Vec<TileType>,
         pub entities: Vec<Option<MEEntity>>,
+        // Set by generated maps, hand-made maps rely on their portal coordinates
+        #[serde(default)]
+        pub player_start: Option<(i32, i32)>,
     }
 
-    pub fn load_from_file(file_name: &'static str) -> MapDescriptor {
+    pub fn load_from_file(file_name: &str) -> MapDescriptor {
         let mut file = File::open(file_name).unwrap();
         let mut contents = String::new();
         file.read_to_string(&mut contents).unwrap();
@@ -96,11 +103,17 @@ pub struct InternalMapState {
     pub map_width: i32,
     pub map_height: i32,
 
-    pub portal_locations: Vec<&'static str>,
+    // Index into portal_locations of the map currently loaded
+    pub current_map: usize,
+    pub portal_locations: Vec<MapSource>,
 
     pub tiles: Vec<Cell<TileType>>,
     //Can have many entities per tile
     pub entities: Vec<HashSet<EntityIndex>>,
+
+    // Tiles the player has seen at some point and tiles the player sees right now
+    pub revealed: Vec<bool>,
+    pub visible: Vec<bool>,
 }
 
 impl InternalMapState {
@@ -109,6 +122,19 @@ impl InternalMapState {
         self.entities = vec![HashSet::new(); self.tiles.len()];
     }
 
+    pub fn clear_visibility(&mut self) {
+        self.revealed = vec![false; self.tiles.len()];
+        self.visible = vec![false; self.tiles.len()];
+    }
+
+    pub fn is_revealed(&self, x: i32, y: i32) -> bool {
+        self.revealed[self.xy_idx(x, y)]
+    }
+
+    pub fn is_visible(&self, x: i32, y: i32) -> bool {
+        self.visible[self.xy_idx(x, y)]
+    }
+
     pub fn xy_idx(&self, x: i32, y: i32) -> usize {
         (y as usize * self.map_width as usize) + x as usize
     }
@@ -145,6 +171,18 @@ impl InternalMapState {
 
 }
 
+impl BaseMap for InternalMapState {
+    fn is_opaque(&self, idx: usize) -> bool {
+        matches!(self.tiles[idx].get(), TileType::Wall(_))
+    }
+}
+
+impl Algorithm2D for InternalMapState {
+    fn dimensions(&self) -> Point {
+        Point::new(self.map_width, self.map_height)
+    }
+}
+
 #[derive(Copy, Clone)]
 pub enum DirectionalInputTypes {
     Attack
@@ -180,6 +218,15 @@ impl EntityStat {
         self.base
     }
 
+    pub fn get_bonus(&self) -> i32 {
+        self.bonus
+    }
+
+    // Bonuses come from equipment and are worked out again whenever it changes
+    pub fn set_bonus(&mut self, bonus: i32) {
+        self.bonus = bonus;
+    }
+
     pub fn get_max(&self) -> i32 {
         self.max
     }
@@ -188,6 +235,12 @@ impl EntityStat {
         self.max = max;
     }
 
+    // Raises the maximum and the current value together
+    pub fn raise(&mut self, amount: i32) {
+        self.set_max(self.max + amount);
+        self.increment(amount);
+    }
+
     pub fn new(name: &'static str, start: i32) -> Self {
         EntityStat {
             name: String::from(name),
@@ -228,6 +281,7 @@ impl Display {
     }
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct SelfDestructAI {
     pub turns_left: i32,
 }
@@ -241,12 +295,64 @@ impl SelfDestructAI {
         
         if sd_ai.turns_left < 0 {
             drop(sd_ai);
+            let pos = state.ecs.get::<BasicEntity>(me).map(|b| b.pos());
+            if let Ok(pos) = pos {
+                state.map_state.set_entity_from_pos(pos, me);
+            }
             state.ecs.despawn(me).expect("Failed to despawn an entity");
         }
     }
 
 }
 
+/*
+ * Tiles an entity can currently see, recomputed with shadowcasting whenever it is dirty.
+ * The player's viewshed also drives the map's visible/revealed tiles.
+ */
+pub struct Viewshed {
+    pub visible_tiles: Vec<Point>,
+    pub range: i32,
+    pub dirty: bool,
+}
+
+impl Viewshed {
+
+    pub fn new(range: i32) -> Self {
+        Viewshed {
+            visible_tiles: vec![],
+            range,
+            dirty: true,
+        }
+    }
+
+    pub fn can_see(&self, pos: (i32, i32)) -> bool {
+        self.visible_tiles.contains(&Point::new(pos.0, pos.1))
+    }
+
+    pub fn on_turn(state: &mut State, me: EntityIndex) {
+        let pos = state.ecs.get_entity_comp(me).pos();
+        let mut viewshed = state.ecs.get_mut::<Viewshed>(me).unwrap();
+
+        if !viewshed.dirty { return; }
+        viewshed.dirty = false;
+
+        let map = &mut state.map_state;
+        viewshed.visible_tiles = rltk::field_of_view(Point::new(pos.0, pos.1), viewshed.range, map);
+        viewshed.visible_tiles.retain(|p| map.in_bounds(*p));
+
+        if state.ecs.get::<Player>(me).is_ok() {
+            map.visible.iter_mut().for_each(|v| *v = false);
+            for p in viewshed.visible_tiles.iter() {
+                let idx = map.xy_idx(p.x, p.y);
+                map.visible[idx] = true;
+                map.revealed[idx] = true;
+            }
+        }
+    }
+
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct BasicEntity {
     pub x: i32,
     pub y: i32,
@@ -261,6 +367,19 @@ pub struct StatBlock {
     pub hp: EntityStat,
 
     pub dead: bool,
+
+    // Progression, only the player's ever changes
+    #[serde(default)]
+    pub xp: i32,
+    #[serde(default = "first_level")]
+    pub level: i32,
+    // Level ups whose reward hasn't been picked yet
+    #[serde(default)]
+    pub unspent_levels: i32,
+}
+
+fn first_level() -> i32 {
+    1
 }
 
 impl Default for StatBlock {
@@ -270,30 +389,37 @@ impl Default for StatBlock {
             def: EntityStat::new("Defense", 0),
             hp: EntityStat::new("Hit Points", 0),
             dead: false,
+            xp: 0,
+            level: first_level(),
+            unspent_levels: 0,
         }
     }
 }
 
+// "ATK: 8 (+3)", the bonus only shows up when there is one
+fn stat_line(label: &str, stat: &EntityStat) -> String {
+    match stat.get_bonus() {
+        0 => format!("{}: {}", label, stat.get_total()),
+        bonus => format!("{}: {} ({:+})", label, stat.get_total(), bonus),
+    }
+}
+
 impl StatBlock {
 
     pub fn make_text_builder(&self, builder: &mut TextBuilder) {
         builder
-            .append(format!("HP: {}", self.hp.get_total()).as_str())
+            .append(stat_line("HP", &self.hp).as_str())
             .ln()
-            .append(format!("ATK: {}", self.atk.get_total()).as_str())
+            .append(stat_line("ATK", &self.atk).as_str())
             .ln()
-            .append(format!("DEF: {}", self.def.get_total()).as_str());
+            .append(stat_line("DEF", &self.def).as_str());
     }
 
+    // Returns whether the hit was lethal, the death itself is dealt with by State::handle_deaths
     pub fn take_damage(&mut self, damage: i32) -> bool {
         self.hp.decrement(max(0, damage - self.def.get_total()) );
 
-        if self.hp.get_total() <= 0 && !self.dead {
-            self.dead = true;
-            return true;
-        }
-
-        return false;
+        self.hp.get_total() <= 0 && !self.dead
     }
 }
 
@@ -317,6 +443,14 @@ impl<T> MyOptionTimeSaver<T> for Option<RefCell<T>> {
 #[derive(Clone, Copy)]
 pub struct ZombieAI;
 
+/*
+ * Extra effects a monster's attacks land on whoever they hit, even when armor soaks up the damage.
+ */
+#[derive(Clone, Serialize, Deserialize)]
+pub struct OnHit {
+    pub effect: SingleTargetEffects,
+}
+
 impl ZombieAI {
 
     pub fn on_turn(state: &mut State, me: EntityIndex) {
@@ -329,8 +463,9 @@ impl ZombieAI {
 
         let dist = ((dx * dx + dy * dy) as f32).sqrt();
 
-        let dx = dx / max(1, dx.abs());
-        let dy = dy / max(1, dy.abs());
+        let sees_player = state.ecs.get::<Viewshed>(me)
+            .map(|v| v.can_see(plr_pos.pos()))
+            .unwrap_or(false);
 
         let me_stats = state.ecs.get_mut::<StatBlock>(me).unwrap();
 
@@ -338,14 +473,34 @@ impl ZombieAI {
 
         if dist - 0.01_f32 < SQRT_2 {
             // Attack here
-            let mut plr_stats = state.get_player_stat_block();
-            plr_stats.take_damage(me_stats.atk.get_total());
-        } else if dist < 5.0 && math_utils::chance(0.9) {
+            let atk = me_stats.atk.get_total();
+            drop(be_comp);
+            drop(plr_pos);
+            drop(me_stats);
+
+            let dealt = {
+                let mut plr_stats = state.get_player_stat_block();
+                let hp_before = plr_stats.hp.get_total();
+                plr_stats.take_damage(atk);
+                hp_before - plr_stats.hp.get_total()
+            };
+
+            let msg = format!("{} hits you for {}", gamelog::describe(state, me), dealt);
+            state.log.push(msg, rltk::RED);
+            let player = state.ecs.get_player_id();
+            state.note_hit(player, gamelog::describe(state, me));
+
+            let on_hit = state.ecs.get::<OnHit>(me).ok().map(|o| o.effect.clone());
+            if let Some(effect) = on_hit {
+                effect.handle_effect(state, me, &Targets::entity(state, player));
+            }
+        } else if sees_player && state.rng.chance(0.9) {
             // Move here
+            let target = plr_pos.pos();
             drop(be_comp);
             drop(plr_pos);
             drop(me_stats);
-            state.move_entity_by(me, dx, dy);
+            CachedPath::step_towards(state, me, target);
         }
     }
 
@@ -356,7 +511,10 @@ pub struct PlayerAI;
 impl PlayerAI {
     pub fn on_turn(state: &mut State, e: EntityIndex) {
         let st_bl = &mut *state.ecs.get_mut::<StatBlock>(e).unwrap();
-        st_bl.hp.increment(1);
+        // Resting can't undo a hit that was already lethal
+        if st_bl.hp.get_total() > 0 {
+            st_bl.hp.increment(1);
+        }
     }
 }
 
@@ -476,14 +634,17 @@ impl Camera {
         (point.0 - self.x_offset - self.x, point.1 - self.y_offset - self.y)
     }
 
-    // fn untransform_point(&self, point: (i32, i32)) -> (i32, i32) {
-    //     (point.0 + self.x, point.1 + self.y)
-    // }
+    //Transforms a screen point back into a world point
+    pub fn untransform_point(&self, point: (i32, i32)) -> (i32, i32) {
+        (point.0 + self.x_offset + self.x, point.1 + self.y_offset + self.y)
+    }
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct EntityView {
     pub name: String,
-    pub art: Arc<XpFile>,
+    // Asset name of the entity's art
+    pub art: String,
 }
 
 impl EntityView {
@@ -492,76 +653,181 @@ impl EntityView {
     }
 }
 
+/*
+ * Decides what a dying entity leaves behind, runs once when the death is handled.
+ */
 pub trait EntityLootHandler {
-    fn handle_loot(&mut self, state: &crate::State);
-}
-
-pub struct SpiderLoot { pub id: EntityIndex, pub max_atk: i32}
-
-impl EntityLootHandler for SpiderLoot {
-    fn handle_loot(&mut self, _state: &crate::State) {
-        // if math_utils::chance(0.3) {
-        //     //Improve player's atk up to 5
-        //     let mut player_stats = state
-        //         .stat_blocks[0]
-        //         .unwrap_ref_mut();
-        //
-        //     let hp_cur_max = player_stats.hp.get_max();
-        //     if hp_cur_max < 30 {
-        //         player_stats.hp.set_max(
-        //             hp_cur_max + 1
-        //         );
-        //     }
-        //
-        //     let atk_cur_max = player_stats.atk.get_max();
-        //     if atk_cur_max < self.max_atk {
-        //         player_stats.atk.set(
-        //             atk_cur_max + 1
-        //         );
-        //     }
-        // }
-    }
-}
-
-pub type EffectLink = Option<Box<dyn EffectHandler + Send + Sync>>;
+    fn handle_loot(&self, state: &mut crate::State, dead: EntityIndex);
+}
+
+pub type EffectLink = Option<Box<SingleTargetEffects>>;
 
 pub trait EffectHandler {
-    fn handle_effect(&self, world: &mut EntityWorld, user: EntityIndex, targets: Vec<EntityIndex>);
+    fn handle_effect(&self, state: &mut State, user: EntityIndex, targets: &Targets);
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub enum SingleTargetEffects {
     NoneEffect,
     DamageTarget(EffectLink, i32),
     HealTarget(EffectLink, i32),
     HealUser(EffectLink, i32),
+    // Turns the walls among the targeted tiles into floor
+    DigTiles(EffectLink),
+    ApplyStatus(EffectLink, StatusEffect),
+}
+
+// Item damage in items.json is what a user with this much attack deals, the player starts with it
+pub const BASELINE_ATK: i32 = 5;
+
+impl SingleTargetEffects {
+
+    // Whether every effect in the chain is one the user would want on themselves, so it needs no aiming
+    pub fn helps_user(&self) -> bool {
+        let (helps, chain) = match self {
+            Self::NoneEffect => (true, &None),
+            Self::HealTarget(chain, _) | Self::HealUser(chain, _) => (true, chain),
+            Self::ApplyStatus(chain, effect) => (effect.kind == crate::status::StatusKind::Regen, chain),
+            Self::DamageTarget(chain, _) | Self::DigTiles(chain) => (false, chain),
+        };
+        helps && chain.as_ref().is_none_or(|c| c.helps_user())
+    }
+
+}
+
+// Restores up to amt hp without going past the maximum
+fn heal(state: &mut State, target: EntityIndex, amt: i32) {
+    let healed = match state.ecs.get_mut::<StatBlock>(target) {
+        Ok(mut stats) if !stats.dead => {
+            let before = stats.hp.get_total();
+            stats.hp.increment(amt);
+            stats.hp.get_total() - before
+        }
+        _ => return,
+    };
+    let msg = format!("{} recovered {} hp", gamelog::describe(state, target), healed);
+    state.log.push(msg, rltk::GREEN);
 }
 
 /*
  * Single target effects apply to all "targets" already in list then simply proceed.
  */
 impl EffectHandler for SingleTargetEffects {
-    fn handle_effect(&self, world: &mut EntityWorld, _user: EntityIndex, targets: Vec<EntityIndex>) {
-        match self {
-            Self::DamageTarget(_chain, amt) => {
-                for target in &targets {
-                    world.get_mut::<StatBlock>(*target).unwrap().hp.decrement(*amt);
+    fn handle_effect(&self, state: &mut State, _user: EntityIndex, targets: &Targets) {
+        let chain = match self {
+            Self::NoneEffect => return,
+            Self::DamageTarget(chain, amt) => {
+                // Every point of attack past the baseline, from level ups, loot or equipment, adds to the hit
+                let atk = state.ecs.get::<StatBlock>(_user).map(|s| s.atk.get_total()).unwrap_or(BASELINE_ATK);
+                let amt = (*amt + atk - BASELINE_ATK).max(0);
+                for target in &targets.entities {
+                    let hit = match state.ecs.get_mut::<StatBlock>(*target) {
+                        Ok(mut stats) => { stats.hp.decrement(amt); true }
+                        Err(_) => false,
+                    };
+                    if !hit { continue; }
+                    let source = if *target == _user { "yourself".to_string() } else { gamelog::describe(state, _user) };
+                    state.note_hit(*target, source);
+                    let msg = format!(
+                        "{} hit {} for {}",
+                        gamelog::describe(state, _user),
+                        gamelog::describe(state, *target),
+                        amt
+                    );
+                    state.log.push(msg, rltk::WHITE);
+                }
+                chain
+            }
+            Self::HealTarget(chain, amt) => {
+                for target in &targets.entities {
+                    heal(state, *target, *amt);
+                }
+                chain
+            }
+            Self::HealUser(chain, amt) => {
+                heal(state, _user, *amt);
+                chain
+            }
+            Self::DigTiles(chain) => {
+                let mut dug = 0;
+                for (x, y) in &targets.tiles {
+                    let tile = &state.map_state.tiles[state.map_state.xy_idx(*x, *y)];
+                    if let TileType::Wall(_) = tile.get() {
+                        tile.set(TileType::Floor(Display {
+                            glyph: rltk::to_cp437('.'),
+                            fg: rltk::GREY,
+                            bg: rltk::BLACK,
+                        }));
+                        dug += 1;
+                    }
+                }
+                if dug > 0 {
+                    state.log.push("The walls crumble away", rltk::GREY);
+                    // Newly opened tiles change what everyone can see
+                    for (_, viewshed) in state.ecs.query_mut::<&mut Viewshed>() {
+                        viewshed.dirty = true;
+                    }
                 }
-                if let Some(chain) = _chain {
-                    chain.handle_effect(world, _user, targets);
+                chain
+            }
+            Self::ApplyStatus(chain, effect) => {
+                for target in &targets.entities {
+                    crate::status::apply(state, *target, effect);
                 }
+                chain
             }
-            _ => {}
+        };
+        if let Some(chain) = chain {
+            chain.handle_effect(state, _user, targets);
         }
     }
 
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Item {
     pub name: String,
-    pub art: Arc<XpFile>,
-    pub effect_chain: Arc<EffectLink>,
+    // Asset name of the item's art
+    pub art: String,
+    // How the item looks while lying on the floor
+    #[serde(default = "item_display")]
+    pub d: Display,
+    pub effect_chain: EffectLink,
+    // Energy using the item costs
+    #[serde(default = "crate::scheduler::default_action_cost")]
+    pub time_cost: i32,
+    #[serde(default)]
+    pub targeting: Targeting,
+    // Uses left before the item is gone, None if it never runs out
+    #[serde(default)]
+    pub charges: Option<i32>,
+    // Which kind of equipment slot the item goes in
+    #[serde(default)]
+    pub slot: EquipSlot,
+    // Added to the wearer's stats while the item is equipped
+    #[serde(default)]
+    pub bonuses: StatBonuses,
+}
+
+impl Item {
+    // The name with the charges left, if it has any
+    pub fn label(&self) -> String {
+        match self.charges {
+            Some(charges) => format!("{} ({})", self.name, charges),
+            None => self.name.clone(),
+        }
+    }
+}
+
+fn item_display() -> Display {
+    Display {
+        glyph: rltk::to_cp437('('),
+        fg: rltk::WHITE,
+        bg: rltk::BLACK,
+    }
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Container {
     pub items: Vec<Item>,
     pub max_items: usize,
@@ -579,19 +845,61 @@ impl Container {
     }
 }
 
+/*
+ * Weapons are anything held in the hand, which covers every item that doesn't say otherwise.
+ */
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize, Default)]
+pub enum EquipSlot {
+    #[default]
+    Weapon,
+    Armor,
+    Ring,
+}
+
+impl EquipSlot {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EquipSlot::Weapon => "Weapon",
+            EquipSlot::Armor => "Armor",
+            EquipSlot::Ring => "Ring",
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize, Default)]
+pub struct StatBonuses {
+    #[serde(default)]
+    pub hp: i32,
+    #[serde(default)]
+    pub atk: i32,
+    #[serde(default)]
+    pub def: i32,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Equipment {
     /* matches an index to the container's item */
     pub equips: Vec<Option<usize>>,
     pub max_equips: usize,
+    /* what kind of item each of equips takes, saves from before slots had kinds only have weapon slots */
+    #[serde(default)]
+    pub slots: Vec<EquipSlot>,
 }
 
 impl Equipment {
+    // Two hands, a body and a finger
     pub fn new() -> Self {
+        let slots = vec![EquipSlot::Weapon, EquipSlot::Weapon, EquipSlot::Armor, EquipSlot::Ring];
         Equipment {
-            equips:vec![None; 3],
-            max_equips: 3
+            equips: vec![None; slots.len()],
+            max_equips: slots.len(),
+            slots,
         }
     }
+
+    pub fn slot_kind(&self, slot: usize) -> EquipSlot {
+        self.slots.get(slot).copied().unwrap_or_default()
+    }
 }
 
 pub trait UInterface {
@@ -626,15 +934,16 @@ impl InventoryUI {
 
     // Equips item from slot into item slot of equipment
     fn equip_item(&self, state: &mut State, slot: usize, item: usize) {
-        let mut player_equips = state.ecs.get_mut::<Equipment>(state.ecs.get_player_id()).unwrap();
-        if let Some(Some(in_slot)) = player_equips.equips.get(item) {
-            if *in_slot == slot {
-                player_equips.equips[item] = None;
-                return;
-            }
+        let player_id = state.ecs.get_player_id();
+        let in_slot = state.ecs.get::<Equipment>(player_id).unwrap().equips.get(item).copied().flatten();
+        if in_slot == Some(slot) {
+            crate::items::unequip(state, player_id, item);
+            return;
+        }
+        // Items that don't fit stay picked so another slot can be tried
+        if crate::items::equip(state, player_id, slot, item) {
+            self.pending_item.replace(None);
         }
-        player_equips.equips[item] = Some(slot);
-        self.pending_item.replace(None);
     }
 }
 
@@ -728,14 +1037,25 @@ pub fn get_index_from_key(key: VirtualKeyCode) -> Option<usize> {
 impl UInterface for InventoryUI {
     fn on_input(&self, state: &mut State, key: Option<VirtualKeyCode>) -> bool {
         if key.is_none() { return false; }
-        const KEY_OPTIONS: [VirtualKeyCode; 32] = enumerate_keys();
         let key = key.unwrap();
 
-        if KEY_OPTIONS.contains(&key) {
-            for (i, k) in KEY_OPTIONS.iter().enumerate() {
-                if key == *k {
-                    self.select_item(state, i);
-                }
+        if let Some(i) = get_index_from_key(key) {
+            self.select_item(state, i);
+        }
+
+        // Uses the selected item on the player, which takes their turn if it's something they can use on themselves
+        if key == VirtualKeyCode::Return {
+            if let Some(item) = *self.pending_item.borrow() {
+                state.perform(crate::actions::Action::UseItem(item));
+                return true;
+            }
+        }
+
+        // Drops the selected item, which takes the player's turn
+        if key == VirtualKeyCode::Delete || key == VirtualKeyCode::Back {
+            if let Some(item) = *self.pending_item.borrow() {
+                state.perform(crate::actions::Action::Drop(item));
+                return true;
             }
         }
 
@@ -754,20 +1074,28 @@ impl UInterface for InventoryUI {
         if let Ok(container) = state.ecs.get::<Container>(self.container_id) {
             for (i, item) in container.items.iter().enumerate() {
                 if i >= KEY_OPTIONS.len() { break; }
-                g_db.print(Point::new(0, i), format!("{}: {}", KEY_OPTIONS[i], item.name).as_str());
+                g_db.print(Point::new(0, i), format!("{}: {}", KEY_OPTIONS[i], item.label()).as_str());
             }
 
             if let Some(pi) = *self.pending_item.borrow() {
                 if let Ok(equipment) = state.ecs.get::<Equipment>(self.container_id) {
                     for (i, item) in equipment.equips.iter().enumerate() {
                         if i >= KEY_OPTIONS.len() { break; }
-                        let item_name = if item.is_some() { container.items[item.unwrap()].name.as_str() } else { "Empty" };
-                        g_db.print(Point::new(16, i), format!("{}: {}", KEY_OPTIONS[i], item_name).as_str());
+                        let item_name = if item.is_some() { container.items[item.unwrap()].label() } else { "Empty".to_string() };
+                        let kind = equipment.slot_kind(i).name();
+                        g_db.print(Point::new(24, i), format!("{}: {:<7}{}", KEY_OPTIONS[i], kind, item_name).as_str());
                     }
                 }
             }
         }
 
+        let hint = if self.pending_item.borrow().is_some() {
+            "Pick a slot to equip it in, Enter to use it, Del to drop it, Esc to close"
+        } else {
+            "Pick an item, Esc to close"
+        };
+        g_db.print(Point::new(0, 38), hint);
+
         g_db.submit(0).expect("Rendering error with draw batch");
 
         rltk::render_draw_buffer(ctx).expect("Rendering error");
@@ -777,3 +1105,41 @@ impl UInterface for InventoryUI {
 
 
 
+
+#[cfg(test)]
+mod tests {
+    use crate::actions::Action;
+    use crate::tests::{room, start, wall};
+
+    #[test]
+    fn walls_block_sight() {
+        let mut map = room(12, 5);
+        for y in 1..4 {
+            map.tiles[(y * map.width + 4) as usize] = wall();
+        }
+        let mut state = start(vec![map]);
+        state.perform(Action::Wait);
+
+        assert!(state.map_state.is_visible(2, 3));
+        assert!(!state.map_state.is_visible(6, 2));
+        assert!(!state.map_state.is_revealed(6, 2));
+    }
+
+    #[test]
+    fn fog_of_war_remembers_what_was_seen() {
+        let mut state = start(vec![room(24, 3)]);
+        state.perform(Action::Wait);
+        assert!(!state.map_state.is_revealed(15, 1));
+
+        for _ in 0..8 {
+            state.perform(Action::Move(1, 0));
+        }
+        assert!(state.map_state.is_visible(15, 1));
+
+        for _ in 0..8 {
+            state.perform(Action::Move(-1, 0));
+        }
+        assert!(!state.map_state.is_visible(15, 1));
+        assert!(state.map_state.is_revealed(15, 1));
+    }
+}
@@ -0,0 +1,147 @@
+use rltk::{DistanceAlg, Point};
+use serde::{Deserialize, Serialize};
+
+use crate::structs::{StatBlock, TileType};
+use crate::{EntityIndex, State};
+
+/*
+ * How an item picks what it hits. Adjacent, Line and Cone are aimed with a direction,
+ * Blast and Cursor with a tile picked by the targeting cursor. Ranges are in tiles.
+ */
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize, Default)]
+pub enum Targeting {
+    // The tile next to the user
+    #[default]
+    Adjacent,
+    // A bolt that stops at the first wall or creature
+    Line { range: i32 },
+    // Everything in sight within a quarter circle
+    Cone { range: i32 },
+    // Everything in sight of the picked tile within radius
+    Blast { range: i32, radius: i32 },
+    // Just the picked tile
+    Cursor { range: i32 },
+}
+
+impl Targeting {
+    pub fn needs_cursor(&self) -> bool {
+        matches!(self, Targeting::Blast { .. } | Targeting::Cursor { .. })
+    }
+}
+
+/*
+ * What an effect lands on, every affected tile and the living creatures standing on them.
+ */
+#[derive(Clone, Default)]
+pub struct Targets {
+    pub tiles: Vec<(i32, i32)>,
+    pub entities: Vec<EntityIndex>,
+}
+
+impl Targets {
+
+    pub fn entity(state: &State, e: EntityIndex) -> Targets {
+        let tiles = state.ecs.get::<crate::structs::BasicEntity>(e)
+            .map(|b| vec![b.pos()])
+            .unwrap_or_default();
+        Targets {
+            tiles,
+            entities: vec![e],
+        }
+    }
+
+    pub fn on_tiles(state: &State, tiles: Vec<(i32, i32)>) -> Targets {
+        let entities = tiles.iter().flat_map(|pos| living_at(state, *pos)).collect();
+        Targets { tiles, entities }
+    }
+
+}
+
+fn in_range(from: (i32, i32), to: (i32, i32), range: i32) -> bool {
+    DistanceAlg::Pythagoras.distance2d(Point::new(from.0, from.1), Point::new(to.0, to.1)) <= range as f32 + 0.5
+}
+
+fn is_wall(state: &State, pos: (i32, i32)) -> bool {
+    matches!(state.map_state.tiles[state.map_state.xy_idx(pos.0, pos.1)].get(), TileType::Wall(_))
+}
+
+// Living creatures on pos, in spawn order so the result doesn't depend on hashing
+fn living_at(state: &State, pos: (i32, i32)) -> Vec<EntityIndex> {
+    let idx = state.map_state.xy_idx(pos.0, pos.1);
+    let mut living = state.map_state.entities[idx]
+        .iter()
+        .copied()
+        .filter(|e| state.ecs.get::<StatBlock>(*e).map(|s| !s.dead).unwrap_or(false))
+        .collect::<Vec<_>>();
+    living.sort_by_key(|e| e.to_bits());
+    living
+}
+
+// Sorted, field_of_view gives the tiles in no particular order
+fn in_sight(state: &State, from: (i32, i32), range: i32) -> Vec<(i32, i32)> {
+    let mut tiles = rltk::field_of_view(Point::new(from.0, from.1), range, &state.map_state)
+        .into_iter()
+        .map(|p| (p.x, p.y))
+        .filter(|p| in_range(from, *p, range))
+        .collect::<Vec<_>>();
+    tiles.sort();
+    tiles
+}
+
+/*
+ * The tiles hit when aimed from `from` with `offset`, a unit direction for the directional modes
+ * and the offset to the picked tile for the cursor ones. Empty if the picked tile is out of range or sight.
+ */
+pub fn affected_tiles(state: &State, from: (i32, i32), targeting: Targeting, offset: (i32, i32)) -> Vec<(i32, i32)> {
+    let aimed = (from.0 + offset.0, from.1 + offset.1);
+    let in_bounds = |p: &(i32, i32)| p.0 >= 0 && p.0 < state.map_state.map_width && p.1 >= 0 && p.1 < state.map_state.map_height;
+
+    match targeting {
+        Targeting::Adjacent => {
+            vec![aimed].into_iter().filter(in_bounds).collect()
+        }
+        Targeting::Line { range } => {
+            let dir = (offset.0.signum(), offset.1.signum());
+            let mut tiles = vec![];
+            for step in 1..=range {
+                let p = (from.0 + dir.0 * step, from.1 + dir.1 * step);
+                if !in_bounds(&p) {
+                    break;
+                }
+                tiles.push(p);
+                if is_wall(state, p) || !living_at(state, p).is_empty() {
+                    break;
+                }
+            }
+            tiles
+        }
+        Targeting::Cone { range } => {
+            let dir = (offset.0.signum() as f32, offset.1.signum() as f32);
+            let dir_len = (dir.0 * dir.0 + dir.1 * dir.1).sqrt();
+            if dir_len == 0.0 {
+                return vec![];
+            }
+            in_sight(state, from, range)
+                .into_iter()
+                .filter(|p| {
+                    let v = ((p.0 - from.0) as f32, (p.1 - from.1) as f32);
+                    let v_len = (v.0 * v.0 + v.1 * v.1).sqrt();
+                    // Within 45 degrees either side of the aim
+                    v_len > 0.0 && (v.0 * dir.0 + v.1 * dir.1) / (v_len * dir_len) >= 0.7
+                })
+                .collect()
+        }
+        Targeting::Blast { range, radius } => {
+            if !in_sight(state, from, range).contains(&aimed) {
+                return vec![];
+            }
+            in_sight(state, aimed, radius)
+        }
+        Targeting::Cursor { range } => {
+            if !in_sight(state, from, range).contains(&aimed) {
+                return vec![];
+            }
+            vec![aimed]
+        }
+    }
+}
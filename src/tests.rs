@@ -0,0 +1,778 @@
+/*
+ * Drives the game through State::perform on small in-memory maps, no window involved.
+ */
+
+use crate::actions::Action;
+use crate::map_editor::MEEntity;
+use crate::mapgen::MapSource;
+use crate::math_utils::GameRng;
+use crate::structs::map_utils::MapDescriptor;
+use crate::structs::*;
+use crate::{EntityIndex, State};
+
+pub(crate) fn floor() -> TileType {
+    TileType::Floor(Display { glyph: '.' as u16, fg: rltk::WHITE, bg: rltk::BLACK })
+}
+
+pub(crate) fn wall() -> TileType {
+    TileType::Wall(Display { glyph: '#' as u16, fg: rltk::WHITE, bg: rltk::BLACK })
+}
+
+// A walled in room of floor with the player starting at (1, 1)
+pub(crate) fn room(width: i32, height: i32) -> MapDescriptor {
+    let tiles = (0..width * height)
+        .map(|i| {
+            let (x, y) = (i % width, i / width);
+            if x == 0 || y == 0 || x == width - 1 || y == height - 1 { wall() } else { floor() }
+        })
+        .collect();
+
+    MapDescriptor {
+        width,
+        height,
+        tiles,
+        entities: vec![None; (width * height) as usize],
+        player_start: Some((1, 1)),
+    }
+}
+
+pub(crate) fn place(map: &mut MapDescriptor, x: i32, y: i32, name: &str) {
+    map.entities[(y * map.width + x) as usize] = Some(MEEntity {
+        d: Display { glyph: '?' as u16, fg: rltk::WHITE, bg: rltk::BLACK },
+        name: name.to_string(),
+    });
+}
+
+pub(crate) fn start(maps: Vec<MapDescriptor>) -> State {
+    let stats = StatBlock {
+        hp: EntityStat::new("Hit Points", 100),
+        def: EntityStat::new("Defense", 3),
+        atk: EntityStat::new("Attack", 5),
+        ..Default::default()
+    };
+    State::start_new_game(0, GameRng::seeded(7), maps.into_iter().map(MapSource::Inline).collect(), stats)
+}
+
+pub(crate) fn player_pos(state: &State) -> (i32, i32) {
+    state.ecs.get_player().pos()
+}
+
+pub(crate) fn entity_at(state: &State, x: i32, y: i32) -> Option<EntityIndex> {
+    let player = state.ecs.get_player_id();
+    let idx = state.map_state.xy_idx(x, y);
+    state.map_state.entities[idx].iter().copied().find(|e| *e != player)
+}
+
+pub(crate) fn hp_of(state: &State, e: EntityIndex) -> i32 {
+    state.ecs.get::<StatBlock>(e).unwrap().hp.get_total()
+}
+
+// Puts the starting sword in the first equipment slot
+fn equip_sword(state: &mut State) {
+    let player = state.ecs.get_player_id();
+    state.ecs.get_mut::<Equipment>(player).unwrap().equips[0] = Some(0);
+}
+
+#[test]
+fn moves_onto_floor() {
+    let mut state = start(vec![room(6, 6)]);
+    state.perform(Action::Move(1, 0));
+    state.perform(Action::Move(1, 1));
+    assert_eq!(player_pos(&state), (3, 2));
+}
+
+#[test]
+fn walls_block_movement() {
+    let mut state = start(vec![room(6, 6)]);
+    state.perform(Action::Move(-1, 0));
+    state.perform(Action::Move(0, -1));
+    state.perform(Action::Move(-1, -1));
+    assert_eq!(player_pos(&state), (1, 1));
+
+    let player = state.ecs.get_player_id();
+    assert!(state.map_state.entities[state.map_state.xy_idx(1, 1)].contains(&player));
+}
+
+#[test]
+fn killed_monster_becomes_corpse() {
+    let mut map = room(6, 6);
+    place(&mut map, 2, 1, "Goblin");
+    let mut state = start(vec![map]);
+    equip_sword(&mut state);
+
+    let goblin = entity_at(&state, 2, 1).expect("Goblin was not spawned");
+    for _ in 0..10 {
+        if state.ecs.get::<StatBlock>(goblin).unwrap().dead {
+            break;
+        }
+        state.perform(Action::UseEquipped(0, (1, 0)));
+    }
+
+    assert!(state.ecs.get::<StatBlock>(goblin).unwrap().dead);
+    assert!(state.ecs.get::<SelfDestructAI>(goblin).is_ok());
+    assert_eq!(state.ecs.get::<BasicEntity>(goblin).unwrap().get_display().glyph, rltk::to_cp437('%'));
+}
+
+#[test]
+fn corpse_is_cleaned_up() {
+    let mut map = room(6, 6);
+    place(&mut map, 4, 4, "Goblin");
+    let mut state = start(vec![map]);
+
+    let goblin = entity_at(&state, 4, 4).expect("Goblin was not spawned");
+    state.ecs.get_mut::<StatBlock>(goblin).unwrap().hp.decrement(100);
+    state.perform(Action::Wait);
+    assert!(state.ecs.get::<SelfDestructAI>(goblin).is_ok());
+
+    for _ in 0..20 {
+        state.perform(Action::Wait);
+    }
+
+    assert!(!state.ecs.contains(goblin));
+    assert!(entity_at(&state, 4, 4).is_none());
+}
+
+#[test]
+fn portal_moves_player_to_destination() {
+    let mut first = room(6, 6);
+    first.tiles[(first.width + 2) as usize] = TileType::Portal(
+        Display { glyph: '>' as u16, fg: rltk::WHITE, bg: rltk::BLACK },
+        1, 3, 4,
+    );
+    place(&mut first, 4, 4, "Goblin");
+
+    let mut second = room(8, 8);
+    second.player_start = None;
+
+    let mut state = start(vec![first, second]);
+    state.perform(Action::Move(1, 0));
+
+    assert_eq!(state.map_state.current_map, 1);
+    assert_eq!(state.map_width(), 8);
+    assert_eq!(player_pos(&state), (3, 4));
+
+    // Only the player comes along
+    assert_eq!(state.ecs.query::<&BasicEntity>().iter().count(), 1);
+    let player = state.ecs.get_player_id();
+    assert!(state.map_state.entities[state.map_state.xy_idx(3, 4)].contains(&player));
+}
+
+#[test]
+fn main_map_leads_to_the_generated_floors() {
+    let mut main = crate::structs::map_utils::load_from_file("main.map");
+    // Just below the portal at the top of the map
+    main.player_start = Some((15, 1));
+    let mut destinations = crate::mapgen::load_destinations("destinations.json");
+    destinations[0] = MapSource::Inline(main);
+    assert!(matches!(destinations[2], MapSource::Generated(_)));
+
+    let stats = StatBlock { hp: EntityStat::new("Hit Points", 100), ..Default::default() };
+    let mut state = State::start_new_game(0, GameRng::seeded(7), destinations, stats);
+    state.perform(Action::Move(0, -1));
+
+    assert_eq!(state.map_state.current_map, 2);
+    assert_eq!(state.map_width(), 48);
+}
+
+#[test]
+fn same_seed_plays_out_the_same_with_a_save_in_between() {
+    use crate::save::SaveGame;
+
+    // Start on a generated floor full of monsters, so the map and every turn depend on the rng
+    let mut destinations = crate::mapgen::load_destinations("destinations.json");
+    destinations[0] = destinations[3].clone();
+    let run = |save_at: Option<usize>| {
+        let stats = StatBlock { hp: EntityStat::new("Hit Points", 1000), ..Default::default() };
+        let mut state = State::start_new_game(0, GameRng::seeded(99), destinations.clone(), stats);
+        for turn in 0..20 {
+            if save_at == Some(turn) {
+                let json = serde_json::to_string(&SaveGame::from_state(&state)).unwrap();
+                let mut loaded: SaveGame = serde_json::from_str(&json).unwrap();
+                assert_eq!(loaded.rng.rng().next_u64(), state.rng.clone().rng().next_u64());
+            }
+            state.perform(Action::Wait);
+        }
+        state
+    };
+
+    let mut saved = run(Some(10));
+    let mut unsaved = run(None);
+
+    let json = |state: &State| serde_json::to_string(&SaveGame::from_state(state)).unwrap();
+    assert_eq!(json(&saved), json(&unsaved));
+    assert_eq!(saved.rng.rng().range(0, 1000000), unsaved.rng.rng().range(0, 1000000));
+}
+
+#[test]
+fn bound_keys_drive_the_player() {
+    use crate::keybindings::{Command, Keybindings, Preset};
+    use rltk::VirtualKeyCode;
+
+    let mut state = start(vec![room(6, 6)]);
+    state.keys = Keybindings::preset(Preset::Wasd);
+
+    state.handle_command(state.keys.command_for(VirtualKeyCode::C).unwrap());
+    assert_eq!(player_pos(&state), (2, 2));
+
+    state.keys.bind(VirtualKeyCode::C, Command::MoveE);
+    state.handle_command(state.keys.command_for(VirtualKeyCode::C).unwrap());
+    assert_eq!(player_pos(&state), (3, 2));
+}
+
+#[test]
+fn replay_ends_where_the_recorded_run_did() {
+    use crate::replay::{InputFrame, Replay};
+    use rltk::VirtualKeyCode::*;
+
+    let mut map = room(8, 6);
+    place(&mut map, 5, 2, "SFElemental");
+    let file_name = std::env::temp_dir().join(format!("replay_test_{}.json", std::process::id()));
+    let file_name = file_name.to_str().unwrap();
+
+    let mut state = start(vec![map]);
+    state.start_recording(file_name);
+    for key in [Numpad6, Numpad3, Numpad6, Numpad5, Numpad5, Numpad5, Numpad5, Numpad8, Numpad5] {
+        state.step(&InputFrame { key: Some(key), click: None });
+    }
+    let hp = hp_of(&state, state.ecs.get_player_id());
+    assert!(hp < 100, "The elemental never got a hit in, so the replay checks nothing");
+
+    let replay = Replay::load_from_file(file_name).unwrap();
+    let _ = std::fs::remove_file(file_name);
+    let mut replayed = State::from_replay(replay);
+    while let Some(input) = replayed.replay.as_mut().filter(|r| !r.is_finished()).map(|r| r.next_input(1000.0)) {
+        replayed.step(&input);
+    }
+
+    assert_eq!(player_pos(&replayed), player_pos(&state));
+    assert_eq!(hp_of(&replayed, replayed.ecs.get_player_id()), hp);
+    assert_eq!(replayed.game_ticks, state.game_ticks);
+}
+
+fn carried_names(state: &State) -> Vec<String> {
+    let player = state.ecs.get_player_id();
+    state.ecs.get::<Container>(player).unwrap().items.iter().map(|i| i.name.clone()).collect()
+}
+
+#[test]
+fn picks_up_items_placed_in_the_map() {
+    let mut map = room(6, 6);
+    place(&mut map, 2, 1, "Ember");
+    let mut state = start(vec![map]);
+    assert!(crate::items::floor_item_at(&state, (2, 1)).is_some());
+
+    state.perform(Action::Move(1, 0));
+    state.perform(Action::PickUp);
+
+    assert_eq!(carried_names(&state), vec!["Rusty Sword", "Smoldering Ember"]);
+    assert!(crate::items::floor_item_at(&state, (2, 1)).is_none());
+}
+
+#[test]
+fn picking_up_nothing_takes_no_time() {
+    let mut state = start(vec![room(6, 6)]);
+    let ticks = state.game_ticks;
+    state.perform(Action::PickUp);
+    assert_eq!(state.game_ticks, ticks);
+    assert_eq!(carried_names(&state).len(), 1);
+}
+
+#[test]
+fn dropping_unequips_and_keeps_other_slots() {
+    let mut map = room(6, 6);
+    place(&mut map, 1, 1, "Ember");
+    let mut state = start(vec![map]);
+    state.perform(Action::PickUp);
+
+    let player = state.ecs.get_player_id();
+    state.ecs.get_mut::<Equipment>(player).unwrap().equips = vec![Some(0), Some(1), None];
+
+    state.perform(Action::Drop(0));
+
+    assert_eq!(carried_names(&state), vec!["Smoldering Ember"]);
+    assert_eq!(state.ecs.get::<Equipment>(player).unwrap().equips, vec![None, Some(0), None]);
+    let dropped = crate::items::floor_item_at(&state, (1, 1)).expect("Nothing was dropped");
+    assert_eq!(state.ecs.get::<crate::items::FloorItem>(dropped).unwrap().item.name, "Rusty Sword");
+}
+
+#[test]
+fn monsters_drop_their_loot() {
+    // Goblins only drop what their table rolls, the club some of the time
+    let clubs = (0..40).map(|seed| {
+        let mut map = room(6, 6);
+        place(&mut map, 4, 4, "Goblin");
+        let mut state = start(vec![map]);
+        state.rng = GameRng::seeded(seed);
+
+        let goblin = entity_at(&state, 4, 4).expect("Goblin was not spawned");
+        state.ecs.get_mut::<StatBlock>(goblin).unwrap().hp.decrement(100);
+        state.perform(Action::Wait);
+
+        let corpse = state.ecs.get::<BasicEntity>(goblin).unwrap().pos();
+        floor_item_names_at(&state, corpse).contains(&"Goblin Club".to_string())
+    }).collect::<Vec<_>>();
+
+    assert!(clubs.iter().any(|c| *c));
+    assert!(!clubs.iter().all(|c| *c));
+}
+
+fn floor_item_names_at(state: &State, pos: (i32, i32)) -> Vec<String> {
+    let idx = state.map_state.xy_idx(pos.0, pos.1);
+    state.map_state.entities[idx]
+        .iter()
+        .filter_map(|e| state.ecs.get::<crate::items::FloorItem>(*e).ok().map(|f| f.item.name.clone()))
+        .collect()
+}
+
+#[test]
+fn king_spider_always_drops_its_mandible() {
+    for seed in 0..5 {
+        let mut map = room(8, 8);
+        place(&mut map, 5, 5, "KSpider");
+        let mut state = start(vec![map]);
+        state.rng = GameRng::seeded(seed);
+
+        let spider = entity_at(&state, 5, 5).expect("King Spider was not spawned");
+        state.ecs.get_mut::<StatBlock>(spider).unwrap().hp.decrement(100);
+        state.perform(Action::Wait);
+
+        let corpse = state.ecs.get::<BasicEntity>(spider).unwrap().pos();
+        assert!(floor_item_names_at(&state, corpse).contains(&"King Spider Mandible".to_string()));
+    }
+}
+
+#[test]
+fn stat_growth_stops_at_cap() {
+    use crate::loot::{LootEntry, LootReward, LootTable, Rarity, StatKind};
+
+    let mut map = room(6, 6);
+    place(&mut map, 4, 4, "Goblin");
+    let mut state = start(vec![map]);
+    let goblin = entity_at(&state, 4, 4).unwrap();
+
+    let table = LootTable {
+        key: "test".to_string(),
+        rolls: 0,
+        guaranteed: vec![LootEntry {
+            reward: LootReward::StatGrowth { stat: StatKind::Atk, amount: 2, cap: 8 },
+            rarity: Rarity::Common,
+            weight: None,
+        }],
+        entries: vec![],
+    };
+
+    for _ in 0..5 {
+        table.handle_loot(&mut state, goblin);
+    }
+    assert_eq!(state.get_player_stat_block().atk.get_total(), 8);
+}
+
+fn give_player(state: &mut State, key: &str) -> usize {
+    let item = state.items.get(key).expect("No such item").to_item();
+    let player = state.ecs.get_player_id();
+    let mut container = state.ecs.get_mut::<Container>(player).unwrap();
+    assert!(container.try_add_item(item).is_none());
+    container.items.len() - 1
+}
+
+#[test]
+fn potion_heals_and_is_used_up() {
+    let mut state = start(vec![room(6, 6)]);
+    state.get_player_stat_block().hp.decrement(20);
+    let potion = give_player(&mut state, "Healing Potion");
+
+    state.perform(Action::UseItem(potion));
+
+    // 8 from the potion and 1 from resting through the turn
+    assert_eq!(state.get_player_stat_block().hp.get_total(), 89);
+    assert_eq!(carried_names(&state), vec!["Rusty Sword"]);
+}
+
+#[test]
+fn attack_items_cannot_be_used_on_yourself() {
+    let mut state = start(vec![room(6, 6)]);
+    let knives = give_player(&mut state, "Throwing Knives");
+    let ticks = state.game_ticks;
+
+    state.perform(Action::UseItem(knives));
+
+    assert_eq!(state.get_player_stat_block().hp.get_total(), 100);
+    assert_eq!(state.game_ticks, ticks);
+    let player = state.ecs.get_player_id();
+    assert_eq!(state.ecs.get::<Container>(player).unwrap().items[knives].charges, Some(3));
+}
+
+#[test]
+fn healing_never_passes_max_hp() {
+    let mut state = start(vec![room(6, 6)]);
+    state.get_player_stat_block().hp.decrement(2);
+    let scroll = give_player(&mut state, "Scroll of Mending");
+
+    state.perform(Action::UseItem(scroll));
+
+    assert_eq!(state.get_player_stat_block().hp.get_total(), 100);
+    let player = state.ecs.get_player_id();
+    assert_eq!(state.ecs.get::<Container>(player).unwrap().items[scroll].charges, Some(1));
+}
+
+#[test]
+fn chained_effects_all_apply() {
+    let mut map = room(6, 6);
+    place(&mut map, 2, 1, "Rock");
+    let mut state = start(vec![map]);
+    let rock = entity_at(&state, 2, 1).unwrap();
+    let dagger = give_player(&mut state, "Vampiric Dagger");
+    let player = state.ecs.get_player_id();
+    state.ecs.get_mut::<Equipment>(player).unwrap().equips[0] = Some(dagger);
+    state.get_player_stat_block().hp.decrement(10);
+
+    let rock_hp = state.ecs.get::<StatBlock>(rock).unwrap().hp.get_total();
+    let hp = state.get_player_stat_block().hp.get_total();
+    state.perform(Action::UseEquipped(0, (1, 0)));
+
+    assert_eq!(state.ecs.get::<StatBlock>(rock).unwrap().hp.get_total(), rock_hp - 4);
+    // The rock's hits don't get past 3 defense, so only the drain and resting show
+    assert_eq!(state.get_player_stat_block().hp.get_total(), hp + 2 + 1);
+}
+
+#[test]
+fn equipped_consumable_runs_out() {
+    let mut map = room(6, 6);
+    place(&mut map, 2, 1, "Rock");
+    let mut state = start(vec![map]);
+    let knives = give_player(&mut state, "Throwing Knives");
+    let player = state.ecs.get_player_id();
+    state.ecs.get_mut::<Equipment>(player).unwrap().equips = vec![Some(knives), Some(0), None];
+
+    for _ in 0..3 {
+        state.perform(Action::UseEquipped(0, (1, 0)));
+    }
+
+    assert_eq!(carried_names(&state), vec!["Rusty Sword"]);
+    assert_eq!(state.ecs.get::<Equipment>(player).unwrap().equips, vec![None, Some(0), None]);
+}
+
+fn equip(state: &mut State, key: &str) {
+    let index = give_player(state, key);
+    let player = state.ecs.get_player_id();
+    state.ecs.get_mut::<Equipment>(player).unwrap().equips[0] = Some(index);
+}
+
+#[test]
+fn bolt_stops_at_the_first_creature() {
+    let mut map = room(10, 4);
+    place(&mut map, 3, 1, "Rock");
+    place(&mut map, 5, 1, "Rock");
+    let mut state = start(vec![map]);
+    let (near, far) = (entity_at(&state, 3, 1).unwrap(), entity_at(&state, 5, 1).unwrap());
+    let (near_hp, far_hp) = (hp_of(&state, near), hp_of(&state, far));
+    equip(&mut state, "Wand of Lightning");
+
+    state.perform(Action::UseEquipped(0, (1, 0)));
+
+    assert_eq!(hp_of(&state, near), near_hp - 7);
+    assert_eq!(hp_of(&state, far), far_hp);
+}
+
+#[test]
+fn blast_hits_everything_around_the_picked_tile() {
+    let mut map = room(10, 10);
+    place(&mut map, 5, 5, "Rock");
+    place(&mut map, 6, 6, "Rock");
+    place(&mut map, 8, 8, "Rock");
+    let mut state = start(vec![map]);
+    let rocks = [(5, 5), (6, 6), (8, 8)].map(|(x, y)| entity_at(&state, x, y).unwrap());
+    let before = rocks.map(|r| hp_of(&state, r));
+    equip(&mut state, "Fire Bomb");
+
+    state.perform(Action::UseEquipped(0, (4, 4)));
+
+    let after = rocks.map(|r| hp_of(&state, r));
+    assert_eq!(after, [before[0] - 8, before[1] - 8, before[2]]);
+}
+
+#[test]
+fn aiming_out_of_range_takes_no_time() {
+    let mut state = start(vec![room(20, 20)]);
+    equip(&mut state, "Fire Bomb");
+    let ticks = state.game_ticks;
+
+    state.perform(Action::UseEquipped(0, (12, 12)));
+
+    assert_eq!(state.game_ticks, ticks);
+    assert_eq!(carried_names(&state).len(), 2);
+}
+
+#[test]
+fn cone_spreads_out_from_the_player() {
+    use crate::targeting::{affected_tiles, Targeting};
+
+    let state = start(vec![room(12, 12)]);
+    let tiles = affected_tiles(&state, (1, 5), Targeting::Cone { range: 4 }, (1, 0));
+
+    assert!(tiles.contains(&(2, 5)));
+    assert!(tiles.contains(&(5, 5)));
+    assert!(tiles.contains(&(3, 6)));
+    assert!(!tiles.contains(&(2, 7)));
+    assert!(!tiles.contains(&(6, 5)));
+    assert!(!tiles.contains(&(1, 5)));
+}
+
+#[test]
+fn digging_turns_walls_into_floor() {
+    let mut state = start(vec![room(8, 4)]);
+    equip(&mut state, "Wand of Digging");
+
+    state.perform(Action::UseEquipped(0, (-1, 0)));
+
+    let idx = state.map_state.xy_idx(0, 1);
+    assert!(matches!(state.map_state.tiles[idx].get(), TileType::Floor(_)));
+}
+
+#[test]
+fn equipment_only_fits_its_kind_of_slot() {
+    let mut state = start(vec![room(6, 6)]);
+    let player = state.ecs.get_player_id();
+    let armor = give_player(&mut state, "Leather Armor");
+
+    assert!(!crate::items::equip(&mut state, player, armor, 0));
+    assert_eq!(state.get_player_stat_block().def.get_bonus(), 0);
+
+    assert!(crate::items::equip(&mut state, player, armor, 2));
+    assert_eq!(state.get_player_stat_block().def.get_bonus(), 2);
+    assert_eq!(state.get_player_stat_block().def.get_total(), 5);
+}
+
+// Hp lost to an adjacent fire elemental over one turn
+fn elemental_hit_with(armor: Option<&str>) -> i32 {
+    let mut map = room(6, 6);
+    place(&mut map, 2, 1, "SFElemental");
+    let mut state = start(vec![map]);
+    if let Some(armor) = armor {
+        let player = state.ecs.get_player_id();
+        let index = give_player(&mut state, armor);
+        assert!(crate::items::equip(&mut state, player, index, 2));
+    }
+
+    state.perform(Action::Wait);
+    let hp = state.get_player_stat_block().hp.get_total();
+    100 - hp
+}
+
+#[test]
+fn armor_blunts_monster_hits() {
+    let unarmored = elemental_hit_with(None);
+    assert!(unarmored > 0);
+    assert!(elemental_hit_with(Some("Leather Armor")) < unarmored);
+}
+
+#[test]
+fn dropping_equipment_takes_its_bonus_away() {
+    let mut state = start(vec![room(6, 6)]);
+    let player = state.ecs.get_player_id();
+    let ring = give_player(&mut state, "Ring of Strength");
+    assert!(crate::items::equip(&mut state, player, ring, 3));
+    assert_eq!(state.get_player_stat_block().atk.get_total(), 8);
+
+    state.perform(Action::Drop(ring));
+
+    assert_eq!(state.get_player_stat_block().atk.get_bonus(), 0);
+    assert_eq!(state.get_player_stat_block().atk.get_total(), 5);
+}
+
+#[test]
+fn attack_bonus_adds_to_item_damage() {
+    let mut map = room(10, 4);
+    place(&mut map, 3, 1, "Rock");
+    let mut state = start(vec![map]);
+    let rock = entity_at(&state, 3, 1).unwrap();
+    let rock_hp = hp_of(&state, rock);
+    let player = state.ecs.get_player_id();
+    let ring = give_player(&mut state, "Ring of Strength");
+    assert!(crate::items::equip(&mut state, player, ring, 3));
+    equip(&mut state, "Wand of Lightning");
+
+    state.perform(Action::UseEquipped(0, (1, 0)));
+
+    assert_eq!(hp_of(&state, rock), rock_hp - 10);
+}
+
+fn status_of(state: &State, e: EntityIndex, kind: crate::status::StatusKind) -> Option<crate::status::StatusEffect> {
+    state.ecs.get::<crate::status::StatusEffects>(e).ok()
+        .and_then(|s| s.effects.iter().find(|effect| effect.kind == kind).cloned())
+}
+
+#[test]
+fn spider_bites_are_poisonous() {
+    let mut map = room(6, 6);
+    place(&mut map, 2, 1, "Spider");
+    let mut state = start(vec![map]);
+
+    state.perform(Action::Wait);
+
+    let player = state.ecs.get_player_id();
+    assert!(status_of(&state, player, crate::status::StatusKind::Poison).is_some());
+}
+
+#[test]
+fn poison_stacks_and_wears_off() {
+    use crate::status::{self, StatusEffect, StatusKind};
+    let mut state = start(vec![room(6, 6)]);
+    let player = state.ecs.get_player_id();
+    let dose = StatusEffect { kind: StatusKind::Poison, turns: 3, potency: 2 };
+    status::apply(&mut state, player, &dose);
+    status::apply(&mut state, player, &dose);
+    assert_eq!(status_of(&state, player, StatusKind::Poison).unwrap().potency, 4);
+
+    state.perform(Action::Wait);
+    // 4 from the poison and 1 back from resting
+    assert_eq!(state.get_player_stat_block().hp.get_total(), 97);
+
+    state.perform(Action::Wait);
+    state.perform(Action::Wait);
+    assert!(status_of(&state, player, StatusKind::Poison).is_none());
+}
+
+#[test]
+fn stunned_monsters_lose_their_turns() {
+    use crate::status::{self, StatusEffect, StatusKind};
+    let mut map = room(6, 6);
+    place(&mut map, 2, 1, "SFElemental");
+    let mut state = start(vec![map]);
+    let elemental = entity_at(&state, 2, 1).unwrap();
+    // Freshly spawned monsters can get two actions in before the player's wait is over
+    status::apply(&mut state, elemental, &StatusEffect { kind: StatusKind::Stun, turns: 3, potency: 1 });
+    state.get_player_stat_block().hp.decrement(50);
+
+    state.perform(Action::Wait);
+
+    assert_eq!(state.get_player_stat_block().hp.get_total(), 51);
+    assert!(status_of(&state, elemental, StatusKind::Stun).is_some());
+}
+
+#[test]
+fn stunned_player_sits_a_turn_out() {
+    use crate::status::{self, StatusEffect, StatusKind};
+    let mut state = start(vec![room(6, 6)]);
+    let player = state.ecs.get_player_id();
+    status::apply(&mut state, player, &StatusEffect { kind: StatusKind::Stun, turns: 1, potency: 1 });
+
+    let before = state.game_ticks;
+    state.perform(Action::Wait);
+
+    assert_eq!(state.game_ticks - before, 2 * crate::scheduler::TICKS_PER_TURN);
+    assert!(status_of(&state, player, StatusKind::Stun).is_none());
+}
+
+#[test]
+fn regeneration_heals_over_time() {
+    let mut state = start(vec![room(6, 6)]);
+    state.get_player_stat_block().hp.decrement(30);
+    let potion = give_player(&mut state, "Potion of Regeneration");
+
+    state.perform(Action::UseItem(potion));
+    state.perform(Action::Wait);
+
+    // 2 a turn from the potion and 1 from resting, twice
+    assert_eq!(state.get_player_stat_block().hp.get_total(), 76);
+}
+
+#[test]
+fn kills_award_xp() {
+    let mut map = room(6, 6);
+    place(&mut map, 4, 4, "Goblin");
+    let mut state = start(vec![map]);
+    let goblin = entity_at(&state, 4, 4).unwrap();
+    let worth = crate::progression::kill_xp(&state.ecs.get::<StatBlock>(goblin).unwrap());
+    state.ecs.get_mut::<StatBlock>(goblin).unwrap().hp.decrement(100);
+
+    state.perform(Action::Wait);
+
+    assert_eq!(state.get_player_stat_block().xp, worth);
+}
+
+#[test]
+fn level_ups_are_spent_on_stats() {
+    use crate::loot::StatKind;
+    use crate::progression::{gain_xp, spend_level, xp_to_reach};
+    let mut state = start(vec![room(6, 6)]);
+
+    gain_xp(&mut state, xp_to_reach(3));
+    assert_eq!(state.get_player_stat_block().level, 3);
+    assert_eq!(state.get_player_stat_block().unspent_levels, 2);
+
+    assert!(spend_level(&mut state, StatKind::Hp));
+    assert!(spend_level(&mut state, StatKind::Def));
+    assert!(!spend_level(&mut state, StatKind::Atk));
+
+    let stats = state.get_player_stat_block().clone();
+    assert_eq!(stats.hp.get_max(), 105);
+    assert_eq!(stats.hp.get_total(), 105);
+    assert_eq!(stats.def.get_total(), 4);
+    assert_eq!(stats.atk.get_total(), 5);
+}
+
+#[test]
+fn attack_level_ups_hit_harder() {
+    let mut map = room(10, 4);
+    place(&mut map, 3, 1, "Rock");
+    let mut state = start(vec![map]);
+    let rock = entity_at(&state, 3, 1).unwrap();
+    let rock_hp = hp_of(&state, rock);
+    equip(&mut state, "Wand of Lightning");
+
+    crate::progression::gain_xp(&mut state, crate::progression::xp_to_reach(2));
+    assert!(crate::progression::spend_level(&mut state, crate::loot::StatKind::Atk));
+    state.perform(Action::UseEquipped(0, (1, 0)));
+
+    assert_eq!(hp_of(&state, rock), rock_hp - 8);
+}
+
+#[test]
+fn progression_survives_saving() {
+    let mut state = start(vec![room(6, 6)]);
+    crate::progression::gain_xp(&mut state, 25);
+
+    let save = crate::save::SaveGame::from_state(&state);
+    let json = serde_json::to_string(&save).unwrap();
+    let loaded: crate::save::SaveGame = serde_json::from_str(&json).unwrap();
+    let player = loaded.entities.iter().find(|e| e.player).unwrap();
+
+    let stats = player.stats.as_ref().unwrap();
+    assert_eq!((stats.xp, stats.level, stats.unspent_levels), (25, 2, 1));
+}
+
+#[test]
+fn player_death_ends_the_game() {
+    let mut map = room(6, 6);
+    place(&mut map, 2, 1, "Tall Dude");
+    let mut state = start(vec![map]);
+    state.get_player_stat_block().hp.decrement(95);
+
+    state.perform(Action::Wait);
+
+    let summary = state.game_over.clone().expect("The player should have died");
+    assert_eq!(summary.killer, "Tall Dude!");
+    assert_eq!(summary.depth, 1);
+    assert!(summary.to_text().contains("Seed 7"));
+    assert!(summary.inventory.contains(&"Rusty Sword".to_string()));
+    assert!(summary.to_text().contains("Killed by Tall Dude! on depth 1"));
+
+    // The player is left as a corpse instead of being despawned
+    let player = state.ecs.get_player_id();
+    assert!(state.ecs.get::<SelfDestructAI>(player).is_err());
+    assert_eq!(state.ecs.get::<BasicEntity>(player).unwrap().get_display().glyph, rltk::to_cp437('%'));
+}
+
+#[test]
+fn poison_is_named_as_the_killer() {
+    use crate::status::{self, StatusEffect, StatusKind};
+    let mut state = start(vec![room(6, 6)]);
+    let player = state.ecs.get_player_id();
+    state.get_player_stat_block().hp.decrement(98);
+    status::apply(&mut state, player, &StatusEffect { kind: StatusKind::Poison, turns: 5, potency: 5 });
+
+    state.perform(Action::Wait);
+
+    assert_eq!(state.game_over.as_ref().map(|s| s.killer.as_str()), Some("poison"));
+}